@@ -0,0 +1,93 @@
+use crate::{point::Point, ray::Ray};
+
+/// Axis-aligned bounding box, used to skip whole groups of objects in the BVH
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Aabb {
+    pub(crate) minimum: Point,
+    pub(crate) maximum: Point,
+}
+
+impl Aabb {
+    pub(crate) fn new(minimum: Point, maximum: Point) -> Self {
+        Self { minimum, maximum }
+    }
+
+    pub(crate) fn surrounding(a: &Aabb, b: &Aabb) -> Self {
+        Self {
+            minimum: a.minimum.min(&b.minimum),
+            maximum: a.maximum.max(&b.maximum),
+        }
+    }
+
+    pub(crate) fn centroid(&self) -> Point {
+        (self.minimum + self.maximum) * 0.5
+    }
+
+    pub(crate) fn longest_axis(&self) -> usize {
+        let extent = self.maximum - self.minimum;
+        if extent.x() > extent.y() && extent.x() > extent.z() {
+            0
+        } else if extent.y() > extent.z() {
+            1
+        } else {
+            2
+        }
+    }
+
+    pub(crate) fn hit(&self, ray: &Ray, mut t_min: f64, mut t_max: f64) -> bool {
+        for axis in 0..3 {
+            let inv_d = 1.0 / ray.direction[axis];
+            let mut t_0 = (self.minimum[axis] - ray.origin[axis]) * inv_d;
+            let mut t_1 = (self.maximum[axis] - ray.origin[axis]) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut t_0, &mut t_1);
+            }
+            t_min = t_0.max(t_min);
+            t_max = t_1.min(t_max);
+            if t_max <= t_min {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Aabb;
+    use crate::{point::Point, ray::Ray, vec3::Vec3};
+
+    #[test]
+    fn hit() {
+        let aabb = Aabb::new(Point::new([-1.0, -1.0, -1.0]), Point::new([1.0, 1.0, 1.0]));
+        let towards = Ray::new(
+            Point::new([0.0, 0.0, -5.0]),
+            Vec3::new([0.0, 0.0, 1.0]),
+            0.0,
+        );
+        let away = Ray::new(
+            Point::new([0.0, 0.0, -5.0]),
+            Vec3::new([0.0, 0.0, -1.0]),
+            0.0,
+        );
+        let beside = Ray::new(
+            Point::new([2.0, 0.0, -5.0]),
+            Vec3::new([0.0, 0.0, 1.0]),
+            0.0,
+        );
+        assert!(aabb.hit(&towards, 0.0, f64::INFINITY));
+        assert!(!aabb.hit(&away, 0.0, f64::INFINITY));
+        assert!(!aabb.hit(&beside, 0.0, f64::INFINITY));
+        assert!(!aabb.hit(&towards, 0.0, 3.0));
+    }
+
+    #[test]
+    fn surrounding() {
+        let a = Aabb::new(Point::new([0.0, 0.0, 0.0]), Point::new([1.0, 1.0, 1.0]));
+        let b = Aabb::new(Point::new([-1.0, 0.5, 0.0]), Point::new([0.5, 2.0, 3.0]));
+        assert_eq!(
+            Aabb::surrounding(&a, &b),
+            Aabb::new(Point::new([-1.0, 0.0, 0.0]), Point::new([1.0, 2.0, 3.0]))
+        );
+    }
+}
@@ -0,0 +1,277 @@
+use crate::{
+    aabb::Aabb,
+    hittable::{HitRecord, Hittable, HittableList},
+    material::Material,
+    point::Point,
+    ray::Ray,
+    utils::{self, Rng},
+    vec3::Vec3,
+};
+use std::sync::Arc;
+
+/// The plane an `AaRect` lies in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Plane {
+    Xy,
+    Xz,
+    Yz,
+}
+
+impl Plane {
+    /// Indices of the two in-plane axes and of the axis along the normal
+    fn axes(self) -> (usize, usize, usize) {
+        match self {
+            Self::Xy => (0, 1, 2),
+            Self::Xz => (0, 2, 1),
+            Self::Yz => (1, 2, 0),
+        }
+    }
+}
+
+/// Axis-aligned rectangle `a0..a1 x b0..b1` at `k` on the remaining axis.
+///
+/// The outward normal points along the positive remaining axis, `flipped`
+/// turns it around.
+pub(crate) struct AaRect {
+    plane: Plane,
+    a: (f64, f64),
+    b: (f64, f64),
+    k: f64,
+    outward: f64,
+    material: Arc<dyn Material>,
+}
+
+impl AaRect {
+    pub(crate) fn new(
+        plane: Plane,
+        a: (f64, f64),
+        b: (f64, f64),
+        k: f64,
+        material: Arc<dyn Material>,
+    ) -> Self {
+        Self {
+            plane,
+            a: (a.0.min(a.1), a.0.max(a.1)),
+            b: (b.0.min(b.1), b.0.max(b.1)),
+            k,
+            outward: 1.0,
+            material,
+        }
+    }
+
+    pub(crate) fn xy(x: (f64, f64), y: (f64, f64), z: f64, material: Arc<dyn Material>) -> Self {
+        Self::new(Plane::Xy, x, y, z, material)
+    }
+
+    pub(crate) fn xz(x: (f64, f64), z: (f64, f64), y: f64, material: Arc<dyn Material>) -> Self {
+        Self::new(Plane::Xz, x, z, y, material)
+    }
+
+    pub(crate) fn yz(y: (f64, f64), z: (f64, f64), x: f64, material: Arc<dyn Material>) -> Self {
+        Self::new(Plane::Yz, y, z, x, material)
+    }
+
+    pub(crate) fn flipped(mut self) -> Self {
+        self.outward = -self.outward;
+        self
+    }
+}
+
+impl Hittable for AaRect {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _rng: &mut Rng) -> Option<HitRecord<'_>> {
+        let (a_axis, b_axis, k_axis) = self.plane.axes();
+
+        let t = (self.k - ray.origin[k_axis]) / ray.direction[k_axis];
+        if !(t_min..=t_max).contains(&t) {
+            return None;
+        }
+
+        let a = ray.origin[a_axis] + t * ray.direction[a_axis];
+        let b = ray.origin[b_axis] + t * ray.direction[b_axis];
+        if a < self.a.0 || a > self.a.1 || b < self.b.0 || b > self.b.1 {
+            return None;
+        }
+
+        let mut normal = [0.0; 3];
+        normal[k_axis] = self.outward;
+        let outward_normal = Vec3::new(normal);
+
+        let uv = (
+            (a - self.a.0) / (self.a.1 - self.a.0),
+            (b - self.b.0) / (self.b.1 - self.b.0),
+        );
+        let mut hit_record = HitRecord::new(
+            ray.at(t),
+            outward_normal,
+            t,
+            uv,
+            false,
+            self.material.as_ref(),
+        );
+        hit_record.set_face_normal(ray, outward_normal);
+        Some(hit_record)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        // Pad the flat side so the box has a non-zero width
+        const PADDING: f64 = 0.0001;
+        let (a_axis, b_axis, k_axis) = self.plane.axes();
+        let mut minimum = [0.0; 3];
+        let mut maximum = [0.0; 3];
+        minimum[a_axis] = self.a.0;
+        maximum[a_axis] = self.a.1;
+        minimum[b_axis] = self.b.0;
+        maximum[b_axis] = self.b.1;
+        minimum[k_axis] = self.k - PADDING;
+        maximum[k_axis] = self.k + PADDING;
+        Some(Aabb::new(Point::new(minimum), Point::new(maximum)))
+    }
+
+    /// Uniform over the area, converted to solid angle
+    fn pdf_value(&self, origin: &Point, direction: &Vec3, rng: &mut Rng) -> f64 {
+        let ray = Ray::new(*origin, *direction, 0.0);
+        match self.hit(&ray, 0.001, f64::INFINITY, rng) {
+            Some(hit_record) => {
+                let area = (self.a.1 - self.a.0) * (self.b.1 - self.b.0);
+                let distance_squared = hit_record.t * hit_record.t * direction.len_square();
+                let cosine = direction.dot(&hit_record.normal).abs() / direction.len();
+                distance_squared / (cosine * area)
+            }
+            None => 0.0,
+        }
+    }
+
+    fn random(&self, origin: &Point, rng: &mut Rng) -> Vec3 {
+        let (a_axis, b_axis, k_axis) = self.plane.axes();
+        let mut point = [0.0; 3];
+        point[a_axis] = self.a.0 + (self.a.1 - self.a.0) * utils::random_float(rng);
+        point[b_axis] = self.b.0 + (self.b.1 - self.b.0) * utils::random_float(rng);
+        point[k_axis] = self.k;
+        Point::new(point) - *origin
+    }
+}
+
+/// Axis-aligned box made of six rectangles with outward facing normals
+pub(crate) struct Cuboid {
+    minimum: Point,
+    maximum: Point,
+    sides: HittableList,
+}
+
+impl Cuboid {
+    pub(crate) fn new(p_0: Point, p_1: Point, material: Arc<dyn Material>) -> Self {
+        let minimum = p_0.min(&p_1);
+        let maximum = p_0.max(&p_1);
+        let (x, y, z) = (
+            (minimum.x(), maximum.x()),
+            (minimum.y(), maximum.y()),
+            (minimum.z(), maximum.z()),
+        );
+
+        let mut sides = HittableList::new();
+        sides.add(Box::new(AaRect::xy(x, y, z.1, Arc::clone(&material))));
+        sides.add(Box::new(
+            AaRect::xy(x, y, z.0, Arc::clone(&material)).flipped(),
+        ));
+        sides.add(Box::new(AaRect::xz(x, z, y.1, Arc::clone(&material))));
+        sides.add(Box::new(
+            AaRect::xz(x, z, y.0, Arc::clone(&material)).flipped(),
+        ));
+        sides.add(Box::new(AaRect::yz(y, z, x.1, Arc::clone(&material))));
+        sides.add(Box::new(AaRect::yz(y, z, x.0, material).flipped()));
+
+        Self {
+            minimum,
+            maximum,
+            sides,
+        }
+    }
+}
+
+impl Hittable for Cuboid {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut Rng) -> Option<HitRecord<'_>> {
+        self.sides.hit(ray, t_min, t_max, rng)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::new(self.minimum, self.maximum))
+    }
+
+    fn pdf_value(&self, origin: &Point, direction: &Vec3, rng: &mut Rng) -> f64 {
+        self.sides.pdf_value(origin, direction, rng)
+    }
+
+    fn random(&self, origin: &Point, rng: &mut Rng) -> Vec3 {
+        self.sides.random(origin, rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AaRect, Cuboid};
+    use crate::{
+        color::Color, hittable::Hittable, material::Lambertian, point::Point, ray::Ray, utils::Rng,
+        vec3::Vec3,
+    };
+    use rand::SeedableRng;
+    use std::sync::Arc;
+
+    fn material() -> Arc<Lambertian> {
+        Arc::new(Lambertian::new(Color::new([0.5, 0.5, 0.5])))
+    }
+
+    #[test]
+    fn rect_hit() {
+        let mut rng = Rng::seed_from_u64(0);
+        let rect = AaRect::xz((0.0, 2.0), (0.0, 4.0), 1.0, material());
+        let ray = Ray::new(
+            Point::new([1.5, 5.0, 1.0]),
+            Vec3::new([0.0, -1.0, 0.0]),
+            0.0,
+        );
+        let hit = rect.hit(&ray, 0.001, f64::INFINITY, &mut rng).unwrap();
+        assert_eq!(hit.t, 4.0);
+        assert_eq!(hit.point, Point::new([1.5, 1.0, 1.0]));
+        assert_eq!(hit.normal, Vec3::new([0.0, 1.0, 0.0]));
+        assert!(hit.front_face);
+        assert_eq!((hit.u, hit.v), (0.75, 0.25));
+
+        let miss = Ray::new(
+            Point::new([3.0, 5.0, 1.0]),
+            Vec3::new([0.0, -1.0, 0.0]),
+            0.0,
+        );
+        assert!(rect.hit(&miss, 0.001, f64::INFINITY, &mut rng).is_none());
+        let parallel = Ray::new(Point::new([1.0, 5.0, 1.0]), Vec3::new([1.0, 0.0, 0.0]), 0.0);
+        assert!(rect
+            .hit(&parallel, 0.001, f64::INFINITY, &mut rng)
+            .is_none());
+    }
+
+    #[test]
+    fn box_normals_point_outward() {
+        let mut rng = Rng::seed_from_u64(0);
+        let cuboid = Cuboid::new(
+            Point::new([1.0, 1.0, 1.0]),
+            Point::new([-1.0, -1.0, -1.0]),
+            material(),
+        );
+        for axis in 0..3 {
+            for sign in [-1.0, 1.0] {
+                let mut origin = [0.0; 3];
+                origin[axis] = 5.0 * sign;
+                let ray = Ray::new(Point::new(origin), -Vec3::new(origin), 0.0);
+
+                let hit = cuboid.hit(&ray, 0.001, f64::INFINITY, &mut rng).unwrap();
+                assert!(hit.front_face);
+                assert_eq!(hit.normal[axis], sign);
+                assert_eq!(hit.t, 0.8);
+            }
+        }
+
+        let inside = Ray::new(Point::new([0.0, 0.0, 0.0]), Vec3::new([0.0, 1.0, 0.0]), 0.0);
+        let hit = cuboid.hit(&inside, 0.001, f64::INFINITY, &mut rng).unwrap();
+        assert!(!hit.front_face);
+        assert_eq!(hit.normal, Vec3::new([0.0, -1.0, 0.0]));
+    }
+}
@@ -0,0 +1,225 @@
+use crate::{
+    camera::Camera, color::Color, hittable::HitRecord, material::MaterialKind, point::Point,
+    vec3::Vec3,
+};
+use clap::ValueEnum;
+use std::ops::Add;
+
+/// What a camera ray hits first, recorded alongside the radiance. Summed
+/// over the samples of a pixel until `average` is called.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct FirstHit {
+    /// Surface color, white where rays leave the scene
+    pub(crate) albedo: Color,
+    /// World space normal facing the camera, zero where rays leave the scene
+    pub(crate) normal: Vec3,
+    /// Camera space depth, the distance in front of the camera along its
+    /// viewing direction. Zero where rays leave the scene.
+    pub(crate) depth: f64,
+    /// World space hit point, the origin where rays leave the scene
+    pub(crate) position: Point,
+    /// `HitRecord::object_id`, zero where rays leave the scene
+    pub(crate) object_id: u32,
+    pub(crate) material: Option<MaterialKind>,
+}
+
+impl FirstHit {
+    pub(crate) fn zero() -> Self {
+        Self {
+            albedo: Color::new([0.0, 0.0, 0.0]),
+            normal: Vec3::new([0.0, 0.0, 0.0]),
+            depth: 0.0,
+            position: Point::new([0.0, 0.0, 0.0]),
+            object_id: 0,
+            material: None,
+        }
+    }
+
+    /// What a ray from `camera` found, `None` if it left the scene
+    pub(crate) fn new(hit: Option<&HitRecord>, camera: &Camera) -> Self {
+        match hit {
+            Some(rec) => Self {
+                albedo: rec.material.albedo(rec),
+                normal: rec.normal,
+                depth: camera.depth(&rec.point),
+                position: rec.point,
+                object_id: rec.object_id,
+                material: Some(rec.material.kind()),
+            },
+            None => Self {
+                albedo: Color::new([1.0, 1.0, 1.0]),
+                ..Self::zero()
+            },
+        }
+    }
+
+    pub(crate) fn average(&self, samples: u32) -> Self {
+        if samples == 0 {
+            return *self;
+        }
+        let scale = 1.0 / samples as f64;
+        Self {
+            albedo: scale * &self.albedo,
+            normal: scale * &self.normal,
+            depth: scale * self.depth,
+            position: scale * &self.position,
+            ..*self
+        }
+    }
+}
+
+/// Sums the continuous values. IDs cannot be averaged, the first sample's
+/// are kept.
+impl Add for FirstHit {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            albedo: self.albedo + other.albedo,
+            normal: self.normal + other.normal,
+            depth: self.depth + other.depth,
+            position: self.position + other.position,
+            ..self
+        }
+    }
+}
+
+/// Arbitrary output variable, an image of something other than radiance
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Aov {
+    Albedo,
+    Normal,
+    Depth,
+    Position,
+    /// Object IDs, numbered in scene order from 1
+    Object,
+    /// Material kinds, 1 to 5 for lambertian, metal, dielectric,
+    /// diffuse_light and isotropic
+    Material,
+}
+
+impl Aov {
+    /// The value of a pixel, as written to HDR images
+    pub(crate) fn value(self, first_hit: &FirstHit) -> Color {
+        match self {
+            Self::Albedo => first_hit.albedo,
+            Self::Normal => first_hit.normal,
+            Self::Depth => Color::new([first_hit.depth; 3]),
+            Self::Position => first_hit.position,
+            Self::Object | Self::Material => Color::new([self.id(first_hit) as f64; 3]),
+        }
+    }
+
+    /// The ID of a pixel, zero unless this is an ID AOV
+    pub(crate) fn id(self, first_hit: &FirstHit) -> u32 {
+        match self {
+            Self::Object => first_hit.object_id,
+            Self::Material => first_hit.material.map_or(0, |kind| kind as u32),
+            _ => 0,
+        }
+    }
+
+    pub(crate) fn is_id(self) -> bool {
+        matches!(self, Self::Object | Self::Material)
+    }
+
+    /// Channel names in EXR images
+    pub(crate) fn channels(self) -> &'static [&'static str] {
+        match self {
+            Self::Albedo => &["R", "G", "B"],
+            Self::Normal | Self::Position => &["X", "Y", "Z"],
+            Self::Depth => &["Z"],
+            Self::Object | Self::Material => &["id"],
+        }
+    }
+
+    /// Maps a value to 8 bits, given the smallest and largest value of every
+    /// channel. Normals go from -1..1 to 0..1, depth from black at the
+    /// camera to white at the farthest point, positions span their bounds.
+    /// IDs get colors that tell them apart, black for none.
+    pub(crate) fn to_rgb8(self, value: Color, (min, max): (Color, Color)) -> [u8; 3] {
+        let linear =
+            |value: Color| [0, 1, 2].map(|c| (255.0 * value[c].clamp(0.0, 1.0)).round() as u8);
+        match self {
+            Self::Albedo => value.to_rgb8(),
+            Self::Normal => linear(value.map(|c| 0.5 * c + 0.5)),
+            Self::Depth if max.x() > 0.0 => linear(value / max.x()),
+            Self::Depth => [0; 3],
+            Self::Position => {
+                let extent = max - min;
+                linear(Color::new([0, 1, 2].map(|c| {
+                    if extent[c] > 0.0 {
+                        (value[c] - min[c]) / extent[c]
+                    } else {
+                        0.0
+                    }
+                })))
+            }
+            Self::Object | Self::Material => id_color(value.x() as u32),
+        }
+    }
+}
+
+/// Color of an ID, far apart for consecutive IDs and never too dark
+fn id_color(id: u32) -> [u8; 3] {
+    if id == 0 {
+        return [0; 3];
+    }
+    // Fibonacci hashing scatters consecutive IDs over all bits
+    let hash = id.wrapping_mul(0x9e37_79b9).to_be_bytes();
+    [0, 1, 2].map(|c| 64 + hash[c] % 192)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{id_color, Aov, FirstHit};
+    use crate::{color::Color, material::MaterialKind, point::Point, vec3::Vec3};
+
+    #[test]
+    fn averages_but_keeps_ids() {
+        let first = FirstHit {
+            albedo: Color::new([0.2, 0.2, 0.2]),
+            normal: Vec3::new([0.0, 1.0, 0.0]),
+            depth: 2.0,
+            position: Point::new([1.0, 0.0, 0.0]),
+            object_id: 3,
+            material: Some(MaterialKind::Metal),
+        };
+        let second = FirstHit {
+            albedo: Color::new([0.4, 0.4, 0.4]),
+            depth: 4.0,
+            ..FirstHit::zero()
+        };
+        let average = (first + second).average(2);
+        assert_eq!(average.depth, 3.0);
+        assert!((average.albedo - Color::new([0.3, 0.3, 0.3])).len() < 1e-12);
+        assert_eq!(average.position, Point::new([0.5, 0.0, 0.0]));
+        assert_eq!(average.object_id, 3);
+        assert_eq!(Aov::Material.id(&average), 2);
+        assert_eq!(Aov::Material.id(&FirstHit::zero()), 0);
+    }
+
+    #[test]
+    fn display() {
+        let range = (Color::new([-2.0, 0.0, 0.0]), Color::new([2.0, 8.0, 0.0]));
+        assert_eq!(
+            Aov::Normal.to_rgb8(Vec3::new([-1.0, 0.0, 1.0]), range),
+            [0, 128, 255]
+        );
+        assert_eq!(
+            Aov::Depth.to_rgb8(Color::new([1.0, 1.0, 1.0]), range),
+            [128; 3]
+        );
+        assert_eq!(
+            Aov::Position.to_rgb8(Point::new([0.0, 8.0, 0.0]), range),
+            [128, 255, 0]
+        );
+
+        assert_eq!(id_color(0), [0; 3]);
+        let colors: Vec<[u8; 3]> = (1..20).map(id_color).collect();
+        for (i, a) in colors.iter().enumerate() {
+            assert!(a.iter().all(|&c| c >= 64));
+            assert!(colors[i + 1..].iter().all(|b| a != b));
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use crate::{color::Color, ray::Ray, vec3::Vec3};
+use std::{
+    f64::consts::PI,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+/// Radiance arriving along rays that leave the scene without hitting anything
+pub(crate) trait Background: Send + Sync {
+    fn color(&self, ray: &Ray) -> Color;
+}
+
+/// Vertical blend between two colors, the default is the white-to-blue sky
+/// from the book
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Gradient {
+    bottom: Color,
+    top: Color,
+}
+
+impl Gradient {
+    pub(crate) fn new(bottom: Color, top: Color) -> Self {
+        Self { bottom, top }
+    }
+}
+
+impl Default for Gradient {
+    fn default() -> Self {
+        Self::new(Color::new([1.0, 1.0, 1.0]), Color::new([0.5, 0.7, 1.0]))
+    }
+}
+
+impl Background for Gradient {
+    fn color(&self, ray: &Ray) -> Color {
+        let unit_direction = ray.direction.as_unit_vec();
+        let t = 0.5 * (unit_direction.y() + 1.0);
+        self.bottom * (1.0 - t) + self.top * t
+    }
+}
+
+/// A constant color, black for scenes lit only by emissive materials
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SolidColor {
+    color: Color,
+}
+
+impl SolidColor {
+    pub(crate) fn new(color: Color) -> Self {
+        Self { color }
+    }
+}
+
+impl Background for SolidColor {
+    fn color(&self, _ray: &Ray) -> Color {
+        self.color
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct EnvironmentError {
+    path: PathBuf,
+    source: image::ImageError,
+}
+
+impl fmt::Display for EnvironmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "failed to load environment map '{}': {}",
+            self.path.display(),
+            self.source
+        )
+    }
+}
+
+/// Equirectangular environment map, usually a captured HDR panorama.
+///
+/// The top row of the image is straight up, the center of the image looks
+/// down the negative z axis. `rotation` turns the map around the y axis.
+pub(crate) struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+    rotation: f64,
+    intensity: f64,
+}
+
+impl EnvironmentMap {
+    pub(crate) fn new(
+        width: usize,
+        height: usize,
+        pixels: Vec<Color>,
+        rotation: f64,
+        intensity: f64,
+    ) -> Self {
+        assert_eq!(pixels.len(), width * height);
+        Self {
+            width,
+            height,
+            pixels,
+            rotation: rotation.to_radians(),
+            intensity,
+        }
+    }
+
+    /// Loads a Radiance `.hdr` (or any other format the `image` crate reads)
+    pub(crate) fn load(
+        path: &Path,
+        rotation: f64,
+        intensity: f64,
+    ) -> Result<Self, EnvironmentError> {
+        let image = image::open(path)
+            .map_err(|source| EnvironmentError {
+                path: path.to_path_buf(),
+                source,
+            })?
+            .into_rgb32f();
+        let (width, height) = image.dimensions();
+        let pixels = image
+            .pixels()
+            .map(|pixel| Color::new([pixel[0] as f64, pixel[1] as f64, pixel[2] as f64]))
+            .collect();
+        Ok(Self::new(
+            width as usize,
+            height as usize,
+            pixels,
+            rotation,
+            intensity,
+        ))
+    }
+
+    fn texel(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x]
+    }
+
+    /// Bilinear lookup, wrapping around horizontally and clamping at the poles
+    fn sample(&self, u: f64, v: f64) -> Color {
+        let x = u * self.width as f64 - 0.5;
+        let y = (v * self.height as f64 - 0.5).clamp(0.0, (self.height - 1) as f64);
+        let (x_0, y_0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x_0, y - y_0);
+
+        let x_0 = (x_0 as i64).rem_euclid(self.width as i64) as usize;
+        let x_1 = (x_0 + 1) % self.width;
+        let y_0 = y_0 as usize;
+        let y_1 = (y_0 + 1).min(self.height - 1);
+
+        let top = self.texel(x_0, y_0) * (1.0 - fx) + self.texel(x_1, y_0) * fx;
+        let bottom = self.texel(x_0, y_1) * (1.0 - fx) + self.texel(x_1, y_1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+}
+
+impl Background for EnvironmentMap {
+    fn color(&self, ray: &Ray) -> Color {
+        let d = ray.direction.as_unit_vec();
+        let (sin, cos) = self.rotation.sin_cos();
+        let d = Vec3::new([cos * d.x() + sin * d.z(), d.y(), -sin * d.x() + cos * d.z()]);
+
+        let u = 0.5 + d.x().atan2(-d.z()) / (2.0 * PI);
+        let v = d.y().clamp(-1.0, 1.0).acos() / PI;
+        self.sample(u, v) * self.intensity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Background, EnvironmentMap, Gradient};
+    use crate::{color::Color, point::Point, ray::Ray, vec3::Vec3};
+
+    fn ray(direction: [f64; 3]) -> Ray {
+        Ray::new(Point::new([0.0, 0.0, 0.0]), Vec3::new(direction), 0.0)
+    }
+
+    #[test]
+    fn gradient() {
+        let gradient = Gradient::new(Color::new([1.0, 0.0, 0.0]), Color::new([0.0, 0.0, 1.0]));
+        assert_eq!(
+            gradient.color(&ray([0.0, 1.0, 0.0])),
+            Color::new([0.0, 0.0, 1.0])
+        );
+        assert_eq!(
+            gradient.color(&ray([0.0, -1.0, 0.0])),
+            Color::new([1.0, 0.0, 0.0])
+        );
+    }
+
+    #[test]
+    fn environment_directions() {
+        // A 4x2 map, the upper row looks up and the lower row looks down
+        let pixels = (0..8).map(|i| Color::new([i as f64, 0.0, 0.0])).collect();
+        let map = EnvironmentMap::new(4, 2, pixels, 0.0, 2.0);
+
+        // Straight up lands in the top row, between the two middle texels
+        assert_eq!(map.color(&ray([0.0, 1.0, 0.0])).x(), 2.0 * 1.5);
+        assert_eq!(map.color(&ray([0.0, -1.0, 0.0])).x(), 2.0 * 5.5);
+
+        let rotated = EnvironmentMap::new(
+            4,
+            2,
+            (0..8).map(|i| Color::new([i as f64, 0.0, 0.0])).collect(),
+            90.0,
+            1.0,
+        );
+        let forward = map.color(&ray([-1.0, 0.0, 0.0]));
+        let turned = rotated.color(&ray([0.0, 0.0, -1.0]));
+        assert!((forward.x() / 2.0 - turned.x()).abs() < 1e-9);
+    }
+}
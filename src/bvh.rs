@@ -0,0 +1,184 @@
+use crate::{
+    aabb::Aabb,
+    hittable::{HitRecord, Hittable, HittableList},
+    ray::Ray,
+    utils::Rng,
+};
+
+/// Bounding volume hierarchy over a list of hittables.
+///
+/// The tree is split at the median centroid along the longest axis of each
+/// node, and children are visited front to back so the far child can be
+/// culled with the nearest hit found so far.
+pub(crate) struct BvhNode {
+    left: Box<dyn Hittable>,
+    right: Option<Box<dyn Hittable>>,
+    bbox: Aabb,
+    axis: usize,
+}
+
+impl BvhNode {
+    pub(crate) fn new(list: HittableList) -> Self {
+        let objects = list
+            .into_objects()
+            .into_iter()
+            .map(|object| {
+                let bbox = object
+                    .bounding_box()
+                    .expect("every object in a BVH needs a bounding box");
+                (bbox, object)
+            })
+            .collect();
+        Self::build(objects)
+    }
+
+    fn build(mut objects: Vec<(Aabb, Box<dyn Hittable>)>) -> Self {
+        assert!(!objects.is_empty(), "cannot build a BVH without objects");
+
+        let centroid_bounds = objects
+            .iter()
+            .map(|(bbox, _)| Aabb::new(bbox.centroid(), bbox.centroid()))
+            .reduce(|a, b| Aabb::surrounding(&a, &b))
+            .unwrap();
+        let axis = centroid_bounds.longest_axis();
+
+        if objects.len() == 1 {
+            let (bbox, left) = objects.pop().unwrap();
+            return Self {
+                left,
+                right: None,
+                bbox,
+                axis,
+            };
+        }
+
+        objects.sort_by(|(a, _), (b, _)| a.centroid()[axis].total_cmp(&b.centroid()[axis]));
+        let upper = objects.split_off(objects.len() / 2);
+
+        let left = Self::subtree(objects);
+        let right = Self::subtree(upper);
+        let bbox = Aabb::surrounding(&left.0, &right.0);
+
+        Self {
+            left: left.1,
+            right: Some(right.1),
+            bbox,
+            axis,
+        }
+    }
+
+    fn subtree(mut objects: Vec<(Aabb, Box<dyn Hittable>)>) -> (Aabb, Box<dyn Hittable>) {
+        if objects.len() == 1 {
+            objects.pop().unwrap()
+        } else {
+            let node = Self::build(objects);
+            (node.bbox, Box::new(node))
+        }
+    }
+}
+
+impl Hittable for BvhNode {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut Rng) -> Option<HitRecord<'_>> {
+        if !self.bbox.hit(ray, t_min, t_max) {
+            return None;
+        }
+
+        let right = match &self.right {
+            Some(right) => right,
+            None => return self.left.hit(ray, t_min, t_max, rng),
+        };
+
+        let (first, second) = if ray.direction[self.axis] < 0.0 {
+            (right, &self.left)
+        } else {
+            (&self.left, right)
+        };
+
+        match first.hit(ray, t_min, t_max, rng) {
+            Some(first_record) => second
+                .hit(ray, t_min, first_record.t, rng)
+                .or(Some(first_record)),
+            None => second.hit(ray, t_min, t_max, rng),
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bbox)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BvhNode;
+    use crate::{
+        hittable::{Hittable, HittableList},
+        material::Lambertian,
+        point::Point,
+        ray::Ray,
+        sphere::Sphere,
+        utils::{self, Rng},
+        vec3::Vec3,
+    };
+    use rand::SeedableRng;
+    use std::sync::Arc;
+
+    fn random_spheres(rng: &mut Rng, count: usize) -> Vec<(Point, f64)> {
+        (0..count)
+            .map(|_| {
+                (
+                    Point::random(rng, -10.0, 10.0),
+                    utils::random_range(rng, 0.1, 1.5),
+                )
+            })
+            .collect()
+    }
+
+    fn list_of(spheres: &[(Point, f64)]) -> HittableList {
+        let mut list = HittableList::new();
+        for (center, radius) in spheres {
+            let material = Lambertian::new(Vec3::new([0.5, 0.5, 0.5]));
+            list.add(Box::new(Sphere::new(*center, *radius, Arc::new(material))));
+        }
+        list
+    }
+
+    #[test]
+    fn matches_linear_list() {
+        let mut rng = Rng::seed_from_u64(2);
+        let spheres = random_spheres(&mut rng, 200);
+        let list = list_of(&spheres);
+        let bvh = BvhNode::new(list_of(&spheres));
+
+        for _ in 0..2000 {
+            let ray = Ray::new(
+                Point::random(&mut rng, -12.0, 12.0),
+                Vec3::random(&mut rng, -1.0, 1.0),
+                0.0,
+            );
+            let expected = list
+                .hit(&ray, 0.001, f64::INFINITY, &mut rng)
+                .map(|rec| rec.t);
+            let actual = bvh
+                .hit(&ray, 0.001, f64::INFINITY, &mut rng)
+                .map(|rec| rec.t);
+            assert_eq!(expected, actual);
+        }
+    }
+
+    #[test]
+    fn single_object() {
+        let mut rng = Rng::seed_from_u64(0);
+        let spheres = [(Point::new([0.0, 0.0, 0.0]), 1.0)];
+        let bvh = BvhNode::new(list_of(&spheres));
+        let ray = Ray::new(
+            Point::new([0.0, 0.0, -5.0]),
+            Vec3::new([0.0, 0.0, 1.0]),
+            0.0,
+        );
+        assert_eq!(
+            bvh.hit(&ray, 0.001, f64::INFINITY, &mut rng)
+                .map(|rec| rec.t),
+            Some(4.0)
+        );
+    }
+}
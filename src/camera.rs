@@ -1,4 +1,9 @@
-use crate::{point::Point, ray::Ray, vec3::Vec3};
+use crate::{
+    point::Point,
+    ray::Ray,
+    utils::{self, Rng},
+    vec3::Vec3,
+};
 
 #[derive(Debug)]
 pub(crate) struct Camera {
@@ -10,34 +15,29 @@ pub(crate) struct Camera {
     v: Vec3,
     w: Vec3,
     lens_radius: f64,
+    shutter_open: f64,
+    shutter_close: f64,
 }
 
 impl Camera {
-    pub(crate) fn new(
-        look_from: Point,
-        look_at: Point,
-        view_up: Vec3,
-        vertical_fov: f64,
-        aspect_ratio: f64,
-        aperture: f64,
-        focus_dist: f64,
-    ) -> Self {
-        let theta = vertical_fov.to_radians();
+    pub(crate) fn from_settings(settings: &CameraSettings, aspect_ratio: f64) -> Self {
+        let theta = settings.vertical_fov.to_radians();
         let h = (theta / 2.0).tan();
         let viewport_height = 2.0 * h;
         let viewport_width = aspect_ratio * viewport_height;
 
-        let w = (look_from - look_at).as_unit_vec();
-        let u = (view_up.cross(&w)).as_unit_vec();
+        let w = (settings.look_from - settings.look_at).as_unit_vec();
+        let u = (settings.view_up.cross(&w)).as_unit_vec();
         let v = w.cross(&u);
 
-        let origin = look_from;
+        let focus_dist = settings.dist_to_focus;
+        let origin = settings.look_from;
         let horizontal = focus_dist * viewport_width * &u;
         let vertical = focus_dist * viewport_height * &v;
         let center = horizontal / 2.0 + vertical / 2.0;
         let lower_left_corner = origin - center - focus_dist * &w;
 
-        let lens_radius = aperture / 2.0;
+        let lens_radius = settings.aperture / 2.0;
 
         Self {
             origin,
@@ -48,15 +48,131 @@ impl Camera {
             v,
             w,
             lens_radius,
+            shutter_open: settings.shutter_open,
+            shutter_close: settings.shutter_close,
         }
     }
 
-    pub(crate) fn get_ray(&self, s: f64, t: f64) -> Ray {
-        let ray_direction = self.lens_radius * &Vec3::random_in_unit_sphere();
+    pub(crate) fn get_ray(&self, s: f64, t: f64, rng: &mut Rng) -> Ray {
+        let ray_direction = self.lens_radius * &Vec3::random_in_unit_sphere(rng);
         let offset = self.u * ray_direction.x() + self.v * ray_direction.y();
         let h = s * &self.horizontal;
         let v = t * &self.vertical;
         let direction = self.lower_left_corner + h + v - self.origin - offset;
-        Ray::new(self.origin + offset, direction)
+        // Not `random_range`, the shutter may open and close at the same time
+        let time =
+            self.shutter_open + (self.shutter_close - self.shutter_open) * utils::random_float(rng);
+        Ray::new(self.origin + offset, direction, time)
+    }
+
+    /// How far `point` is in front of the camera, along the viewing direction
+    pub(crate) fn depth(&self, point: &Point) -> f64 {
+        (self.origin - *point).dot(&self.w)
+    }
+}
+
+/// Camera placement as given on the command line or in a scene file
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct CameraSettings {
+    pub(crate) look_from: Point,
+    pub(crate) look_at: Point,
+    pub(crate) view_up: Vec3,
+    pub(crate) vertical_fov: f64,
+    pub(crate) aperture: f64,
+    pub(crate) dist_to_focus: f64,
+    /// Rays are sent at random times in this interval, moving objects are
+    /// blurred along their path
+    pub(crate) shutter_open: f64,
+    pub(crate) shutter_close: f64,
+}
+
+impl CameraSettings {
+    /// Rejects settings for which `Camera::from_settings` would produce NaN
+    /// rays
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.look_from == self.look_at {
+            return Err("look_from and look_at must be different points".to_string());
+        }
+        if self
+            .view_up
+            .cross(&(self.look_from - self.look_at))
+            .near_zero()
+        {
+            return Err("view_up must not be parallel to the viewing direction".to_string());
+        }
+        if !(self.vertical_fov > 0.0 && self.vertical_fov < 180.0) {
+            return Err(format!(
+                "vertical_fov must be between 0 and 180 degrees, got {}",
+                self.vertical_fov
+            ));
+        }
+        if !(self.aperture >= 0.0 && self.aperture.is_finite()) {
+            return Err(format!(
+                "aperture must not be negative, got {}",
+                self.aperture
+            ));
+        }
+        if !(self.dist_to_focus > 0.0 && self.dist_to_focus.is_finite()) {
+            return Err(format!(
+                "dist_to_focus must be positive, got {}",
+                self.dist_to_focus
+            ));
+        }
+        if !(self.shutter_open.is_finite() && self.shutter_close.is_finite()) {
+            return Err("shutter times must be finite".to_string());
+        }
+        if self.shutter_close < self.shutter_open {
+            return Err(format!(
+                "the shutter must close after it opens, got {} to {}",
+                self.shutter_open, self.shutter_close
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Camera, CameraSettings};
+    use crate::{point::Point, utils::Rng, vec3::Vec3};
+    use rand::SeedableRng;
+
+    fn camera(shutter_open: f64, shutter_close: f64) -> Camera {
+        let settings = CameraSettings {
+            look_from: Point::new([0.0, 0.0, 0.0]),
+            look_at: Point::new([0.0, 0.0, -1.0]),
+            view_up: Vec3::new([0.0, 1.0, 0.0]),
+            vertical_fov: 90.0,
+            aperture: 0.0,
+            dist_to_focus: 1.0,
+            shutter_open,
+            shutter_close,
+        };
+        Camera::from_settings(&settings, 1.0)
+    }
+
+    #[test]
+    fn ray_times() {
+        let mut rng = Rng::seed_from_u64(0);
+        // The default shutter opens and closes at the same time
+        let closed = camera(0.5, 0.5);
+        for _ in 0..10 {
+            assert_eq!(closed.get_ray(0.5, 0.5, &mut rng).time, 0.5);
+        }
+
+        let open = camera(1.0, 2.0);
+        let times: Vec<f64> = (0..100)
+            .map(|_| open.get_ray(0.5, 0.5, &mut rng).time)
+            .collect();
+        assert!(times.iter().all(|time| (1.0..2.0).contains(time)));
+        assert!(times.iter().any(|&time| time < 1.5) && times.iter().any(|&time| time > 1.5));
+    }
+
+    #[test]
+    fn depth() {
+        let camera = camera(0.0, 0.0);
+        assert_eq!(camera.depth(&Point::new([0.0, 0.0, -2.0])), 2.0);
+        // Points off to the side are as deep as the plane they lie in
+        assert_eq!(camera.depth(&Point::new([3.0, -1.0, -2.0])), 2.0);
     }
 }
@@ -0,0 +1,272 @@
+//! Render progress saved to disk, so a render can be resumed after the
+//! process ends.
+//!
+//! A checkpoint holds the command line the render was started with, the seed
+//! included, and the summed radiance and statistics of every pixel. Every
+//! sample draws from its own random stream, picked by the seed, the pixel
+//! and the number of samples the pixel already has, so these are the whole
+//! RNG state: a resumed render gives the same image as one that was never
+//! interrupted.
+
+use crate::render::Framebuffer;
+use std::{
+    fmt, fs,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+/// Identifies the file format and its version
+const MAGIC: &[u8; 8] = b"RTCKPT01";
+
+/// Longest command line argument read back, longer ones mean the file is
+/// corrupt
+const MAX_ARG_LEN: u32 = 1 << 16;
+
+#[derive(Debug)]
+pub(crate) enum CheckpointError {
+    Io(io::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{}", err),
+            Self::Invalid(message) => write!(f, "not a valid checkpoint: {}", message),
+        }
+    }
+}
+
+impl From<io::Error> for CheckpointError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+pub(crate) struct Checkpoint {
+    /// Command line arguments of the render, without the program name
+    pub(crate) args: Vec<String>,
+    /// Whether the framebuffer has first hits for the denoiser and AOVs
+    pub(crate) first_hits: bool,
+    pub(crate) framebuffer: Framebuffer,
+}
+
+impl Checkpoint {
+    /// Saves the progress of a render. The file is replaced only once the
+    /// new checkpoint is complete, so being killed while saving loses
+    /// nothing.
+    pub(crate) fn save(
+        path: &Path,
+        args: &[String],
+        first_hits: bool,
+        framebuffer: &Framebuffer,
+    ) -> io::Result<()> {
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".partial");
+
+        let mut writer = BufWriter::new(fs::File::create(&partial)?);
+        write(&mut writer, args, first_hits, framebuffer)?;
+        writer.into_inner()?.sync_all()?;
+        fs::rename(&partial, path)
+    }
+
+    pub(crate) fn load(path: &Path) -> Result<Self, CheckpointError> {
+        read(&mut BufReader::new(fs::File::open(path)?))
+    }
+}
+
+fn write(
+    writer: &mut impl Write,
+    args: &[String],
+    first_hits: bool,
+    framebuffer: &Framebuffer,
+) -> io::Result<()> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&(args.len() as u32).to_le_bytes())?;
+    for arg in args {
+        writer.write_all(&(arg.len() as u32).to_le_bytes())?;
+        writer.write_all(arg.as_bytes())?;
+    }
+    writer.write_all(&[first_hits as u8])?;
+    for size in [framebuffer.width, framebuffer.height] {
+        writer.write_all(&(size as u64).to_le_bytes())?;
+    }
+    framebuffer.write_pixels(writer)
+}
+
+fn read(reader: &mut impl Read) -> Result<Checkpoint, CheckpointError> {
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(CheckpointError::Invalid(
+            "unknown file format or version".to_string(),
+        ));
+    }
+
+    let count = read_u32(reader)?;
+    let mut args = Vec::with_capacity(count.min(1024) as usize);
+    for _ in 0..count {
+        let len = read_u32(reader)?;
+        if len > MAX_ARG_LEN {
+            return Err(CheckpointError::Invalid(format!(
+                "argument of {} bytes",
+                len
+            )));
+        }
+        let mut bytes = vec![0; len as usize];
+        reader.read_exact(&mut bytes)?;
+        let arg = String::from_utf8(bytes)
+            .map_err(|_| CheckpointError::Invalid("argument is not UTF-8".to_string()))?;
+        args.push(arg);
+    }
+
+    let mut first_hits = [0];
+    reader.read_exact(&mut first_hits)?;
+    let mut sizes = [0; 2];
+    for size in &mut sizes {
+        let mut bytes = [0; 8];
+        reader.read_exact(&mut bytes)?;
+        *size = u64::from_le_bytes(bytes) as usize;
+    }
+    let [width, height] = sizes;
+    if width < 2 || height < 2 || width.saturating_mul(height) > 1 << 30 {
+        return Err(CheckpointError::Invalid(format!(
+            "unlikely image size {}x{}",
+            width, height
+        )));
+    }
+
+    let framebuffer = Framebuffer::read_pixels(width, height, reader)?;
+    Ok(Checkpoint {
+        args,
+        first_hits: first_hits[0] != 0,
+        framebuffer,
+    })
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read, write, CheckpointError, MAGIC};
+    use crate::{
+        background::SolidColor,
+        camera::{Camera, CameraSettings},
+        color::Color,
+        hittable::HittableList,
+        integrator::{Bounces, Heuristic, Integrator},
+        material::Lambertian,
+        point::Point,
+        render::{Framebuffer, Renderer},
+        sphere::Sphere,
+        vec3::Vec3,
+    };
+    use indicatif::ProgressBar;
+    use std::{io::Cursor, sync::Arc};
+
+    fn render(samples: u32, framebuffer: &mut Framebuffer) {
+        let mut world = HittableList::new();
+        world.add(Box::new(Sphere::new(
+            Point::new([0.0, 0.0, -1.0]),
+            0.5,
+            Arc::new(Lambertian::new(Color::new([0.5, 0.5, 0.5]))),
+        )));
+        let world = world.numbered();
+        let settings = CameraSettings {
+            look_from: Point::new([0.0, 0.0, 0.0]),
+            look_at: Point::new([0.0, 0.0, -1.0]),
+            view_up: Vec3::new([0.0, 1.0, 0.0]),
+            vertical_fov: 90.0,
+            aperture: 0.0,
+            dist_to_focus: 1.0,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        };
+        let renderer = Renderer {
+            world: &world,
+            lights: None,
+            background: &SolidColor::new(Color::new([0.5, 0.7, 1.0])),
+            camera: &Camera::from_settings(&settings, 1.0),
+            samples_per_pixel: samples,
+            adaptive: None,
+            bounces: Bounces {
+                max: 10,
+                roulette_after: 3,
+            },
+            integrator: Integrator::Path,
+            heuristic: Heuristic::Power,
+            first_hits: true,
+            seed: 3,
+        };
+        renderer.render(framebuffer, &ProgressBar::hidden(), |_| ());
+    }
+
+    #[test]
+    fn resume_matches_uninterrupted_render() {
+        let mut framebuffer = Framebuffer::new(8, 6);
+        render(3, &mut framebuffer);
+
+        let args = vec!["--samples".to_string(), "3".to_string()];
+        let mut buffer = vec![];
+        write(&mut buffer, &args, true, &framebuffer).unwrap();
+        let mut checkpoint = read(&mut Cursor::new(buffer)).unwrap();
+        assert_eq!(checkpoint.args, args);
+        assert!(checkpoint.first_hits);
+        render(7, &mut checkpoint.framebuffer);
+
+        let mut uninterrupted = Framebuffer::new(8, 6);
+        render(7, &mut uninterrupted);
+        let resumed = &checkpoint.framebuffer;
+        assert!(resumed.samples().all(|samples| samples == 7));
+        assert!(resumed.colors().eq(uninterrupted.colors()));
+        assert!(resumed.variances().eq(uninterrupted.variances()));
+        assert!(resumed.first_hits().eq(uninterrupted.first_hits()));
+        assert!(resumed
+            .first_hits()
+            .any(|first_hit| first_hit.object_id == 1));
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let result = read(&mut Cursor::new(b"P6\n2 2\n255\n".to_vec()));
+        assert!(matches!(result, Err(CheckpointError::Invalid(_))));
+
+        let mut framebuffer = Framebuffer::new(4, 4);
+        render(1, &mut framebuffer);
+        let mut buffer = vec![];
+        write(&mut buffer, &[], false, &framebuffer).unwrap();
+        buffer.truncate(buffer.len() - 10);
+        assert!(matches!(
+            read(&mut Cursor::new(buffer)),
+            Err(CheckpointError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_made_up_sizes() {
+        // One argument that claims to be 4 GiB long
+        let mut header = MAGIC.to_vec();
+        header.extend(1u32.to_le_bytes());
+        header.extend(u32::MAX.to_le_bytes());
+        header.extend(b"--samples");
+        assert!(matches!(
+            read(&mut Cursor::new(header)),
+            Err(CheckpointError::Invalid(_))
+        ));
+
+        // The largest image allowed, without its pixels
+        let mut header = MAGIC.to_vec();
+        header.extend(0u32.to_le_bytes());
+        header.push(0);
+        header.extend((1u64 << 15).to_le_bytes());
+        header.extend((1u64 << 15).to_le_bytes());
+        assert!(matches!(
+            read(&mut Cursor::new(header)),
+            Err(CheckpointError::Io(_))
+        ));
+    }
+}
@@ -0,0 +1,770 @@
+use crate::{
+    aov::Aov,
+    background::{Background, EnvironmentError, EnvironmentMap, Gradient, SolidColor},
+    camera::CameraSettings,
+    checkpoint::Checkpoint,
+    color::Color,
+    denoise::Denoiser,
+    integrator::{Bounces, Heuristic, Integrator},
+    output::{ExrCompression, ExrPrecision, ImageFormat, OutputSettings},
+    point::Point,
+    render::Adaptive,
+    tonemap::{ToneMapper, ToneMapping},
+    vec3::Vec3,
+};
+use clap::{error::ErrorKind, CommandFactory, Parser, ValueEnum};
+use std::{
+    env, fmt,
+    path::{Path, PathBuf},
+};
+
+/// Renders the final scene of "Ray Tracing in One Weekend" or a scene file
+#[derive(Debug, Parser)]
+#[command(version, allow_negative_numbers = true, args_override_self = true)]
+pub(crate) struct Args {
+    /// Output image, the format is picked from the extension: .png or .ppm,
+    /// or .exr or .pfm for linear HDR
+    #[arg(short, long, default_value = "image.png")]
+    pub(crate) output: PathBuf,
+
+    /// Curve fitting the radiance into PNG and PPM images, HDR images are
+    /// written as they are
+    #[arg(long, value_enum, default_value_t = ToneMapper::Clamp)]
+    pub(crate) tone_map: ToneMapper,
+
+    /// Exposure adjustment in stops before tone mapping, +1 doubles the
+    /// brightness
+    #[arg(long, default_value_t = 0.0)]
+    pub(crate) exposure: f64,
+
+    /// Luminance that `--tone-map reinhard-extended` maps to white
+    #[arg(long, default_value_t = 4.0)]
+    pub(crate) white_point: f64,
+
+    /// Sample type of EXR images
+    #[arg(long, value_enum, default_value_t = ExrPrecision::Half)]
+    pub(crate) exr_precision: ExrPrecision,
+
+    /// Compression of EXR images
+    #[arg(long, value_enum, default_value_t = ExrCompression::Zip)]
+    pub(crate) exr_compression: ExrCompression,
+
+    /// Image width in pixels
+    #[arg(short, long, default_value_t = 1200)]
+    pub(crate) width: usize,
+
+    /// Width divided by height, either as a number or as `w:h`
+    #[arg(long, default_value = "3:2", value_parser = parse_aspect_ratio)]
+    pub(crate) aspect_ratio: f64,
+
+    /// Samples taken for every pixel, the most a pixel takes with
+    /// `--adaptive-threshold`
+    #[arg(short, long, default_value_t = 10)]
+    pub(crate) samples: u32,
+
+    /// Stop sampling a pixel once the standard error of its brightness is
+    /// below this fraction of the brightness, e.g. 0.01
+    #[arg(long)]
+    pub(crate) adaptive_threshold: Option<f64>,
+
+    /// Samples every pixel takes before adaptive sampling may stop
+    #[arg(long, default_value_t = 16, requires = "adaptive_threshold")]
+    pub(crate) min_samples: u32,
+
+    /// Also write an image of how many samples each pixel took
+    #[arg(long, requires = "adaptive_threshold")]
+    pub(crate) sample_heatmap: Option<PathBuf>,
+
+    /// Filter the noise out of the image, guided by the albedo, normal and
+    /// depth of what each pixel sees
+    #[arg(long)]
+    pub(crate) denoise: bool,
+
+    /// Passes of the denoising filter, each one reaches twice as far
+    #[arg(long, default_value_t = 5, requires = "denoise")]
+    pub(crate) denoise_iterations: u32,
+
+    /// How strongly the denoiser smooths, higher values blur more
+    #[arg(long, default_value_t = 1.0, requires = "denoise")]
+    pub(crate) denoise_strength: f64,
+
+    /// Also write what camera rays hit first as `kind=path`, e.g.
+    /// `normal=normal.exr`. Kinds are albedo, normal, depth, position, object
+    /// and material. Can be repeated
+    #[arg(long = "aov", value_name = "KIND=PATH", value_parser = parse_aov_output)]
+    pub(crate) aovs: Vec<(Aov, PathBuf)>,
+
+    /// Also write the image and all AOVs as layers of one EXR file
+    #[arg(long)]
+    pub(crate) aov_exr: Option<PathBuf>,
+
+    /// Maximum number of bounces per ray
+    #[arg(long, default_value_t = 50)]
+    pub(crate) max_depth: u64,
+
+    /// Bounces before Russian roulette may end a path early. It does not
+    /// change the expected image, set it to `--max-depth` to turn it off
+    #[arg(long, default_value_t = 5)]
+    pub(crate) rr_depth: u64,
+
+    /// Algorithm estimating the light along each camera ray
+    #[arg(long, value_enum, default_value_t = Integrator::Mixture)]
+    pub(crate) integrator: Integrator,
+
+    /// How the path integrator weights light and BSDF samples
+    #[arg(long, value_enum, default_value_t = Heuristic::Power)]
+    pub(crate) mis_heuristic: Heuristic,
+
+    /// Camera position as `x,y,z`
+    #[arg(long, default_value = "13,2,3", value_parser = parse_vec3, allow_hyphen_values = true)]
+    pub(crate) look_from: Point,
+
+    /// Point the camera looks at as `x,y,z`
+    #[arg(long, default_value = "0,0,0", value_parser = parse_vec3, allow_hyphen_values = true)]
+    pub(crate) look_at: Point,
+
+    /// Up direction of the camera as `x,y,z`
+    #[arg(long, default_value = "0,1,0", value_parser = parse_vec3, allow_hyphen_values = true)]
+    pub(crate) view_up: Vec3,
+
+    /// Vertical field of view in degrees
+    #[arg(long, default_value_t = 20.0)]
+    pub(crate) vertical_fov: f64,
+
+    /// Lens diameter, 0 gives a pinhole camera without depth of field
+    #[arg(long, default_value_t = 0.1)]
+    pub(crate) aperture: f64,
+
+    /// Distance from the camera to the plane in perfect focus
+    #[arg(long, default_value_t = 10.0)]
+    pub(crate) dist_to_focus: f64,
+
+    /// Time the camera shutter opens
+    #[arg(long, default_value_t = 0.0)]
+    pub(crate) shutter_open: f64,
+
+    /// Time the camera shutter closes. The bouncing spheres of the final
+    /// scene move from time 0 to 1, `--shutter-close 1` blurs them
+    #[arg(long, default_value_t = 0.0)]
+    pub(crate) shutter_close: f64,
+
+    /// Number of render threads, defaults to one per core
+    #[arg(short = 'j', long)]
+    pub(crate) threads: Option<usize>,
+
+    /// Solid background color as `r,g,b`, replacing the sky gradient. Use
+    /// `0,0,0` for scenes lit only by lights
+    #[arg(long, value_parser = parse_vec3, allow_hyphen_values = true, group = "sky")]
+    pub(crate) background: Option<Color>,
+
+    /// Vertical background gradient as `r,g,b:r,g,b`, from bottom to top
+    #[arg(long, value_parser = parse_gradient, allow_hyphen_values = true, group = "sky")]
+    pub(crate) gradient: Option<(Color, Color)>,
+
+    /// Equirectangular environment map (Radiance .hdr) lighting the scene
+    #[arg(long, group = "sky")]
+    pub(crate) environment: Option<PathBuf>,
+
+    /// Rotation of the environment map around the up axis, in degrees
+    #[arg(long, default_value_t = 0.0, requires = "environment")]
+    pub(crate) environment_rotation: f64,
+
+    /// Brightness multiplier for the environment map
+    #[arg(long, default_value_t = 1.0, requires = "environment")]
+    pub(crate) environment_intensity: f64,
+
+    /// Scene description file (TOML); its camera replaces the camera options
+    #[arg(long)]
+    pub(crate) scene: Option<PathBuf>,
+
+    /// Seed for the scene layout and all sampling, the same seed and settings
+    /// reproduce the same image. A random seed is used if omitted
+    #[arg(long)]
+    pub(crate) seed: Option<u64>,
+
+    /// Save the progress to this file every `--checkpoint-interval` seconds
+    /// and when done, so the render can be resumed
+    #[arg(long)]
+    pub(crate) checkpoint: Option<PathBuf>,
+
+    /// Seconds between checkpoints, the image is also written every time
+    #[arg(long, default_value_t = 60.0)]
+    pub(crate) checkpoint_interval: f64,
+
+    /// Continue the render saved in a checkpoint, e.g. to a higher
+    /// `--samples`. Its settings are used, options given here may change the
+    /// sample counts, outputs, tone mapping, denoising and threads. Progress
+    /// is saved back to it unless `--checkpoint` says otherwise
+    #[arg(long)]
+    pub(crate) resume: Option<PathBuf>,
+
+    /// The arguments of this render with its seed, which checkpoints keep
+    #[arg(skip)]
+    pub(crate) command_line: Vec<String>,
+}
+
+impl Args {
+    /// The background chosen on the command line, if any
+    pub(crate) fn background(&self) -> Result<Option<Box<dyn Background>>, EnvironmentError> {
+        if let Some(color) = self.background {
+            return Ok(Some(Box::new(SolidColor::new(color))));
+        }
+        if let Some((bottom, top)) = self.gradient {
+            return Ok(Some(Box::new(Gradient::new(bottom, top))));
+        }
+        if let Some(path) = &self.environment {
+            let map =
+                EnvironmentMap::load(path, self.environment_rotation, self.environment_intensity)?;
+            return Ok(Some(Box::new(map)));
+        }
+        Ok(None)
+    }
+
+    /// Parses the command line and exits with a usage error if the settings
+    /// cannot produce an image. With `--resume`, the checkpoint's arguments
+    /// come first and the checkpoint is returned too.
+    pub(crate) fn parse_and_validate() -> (Self, Option<Checkpoint>) {
+        let mut command_line: Vec<String> = env::args().skip(1).collect();
+        let mut checkpoint = None;
+        if let Some(path) = Self::resume_path(&command_line) {
+            match Checkpoint::load(&path) {
+                Ok(loaded) => {
+                    command_line.splice(0..0, loaded.args.iter().cloned());
+                    checkpoint = Some(loaded);
+                }
+                Err(err) => Self::command()
+                    .error(
+                        ErrorKind::Io,
+                        format!("cannot resume from '{}': {}", path.display(), err),
+                    )
+                    .exit(),
+            }
+        }
+
+        let program = env::args().next().unwrap_or_default();
+        let mut args = Self::parse_from(std::iter::once(&program).chain(&command_line));
+        if args.seed.is_none() {
+            let seed: u64 = rand::random();
+            command_line.extend(["--seed".to_string(), seed.to_string()]);
+            args.seed = Some(seed);
+        }
+        args.command_line = command_line;
+
+        let validation = match &checkpoint {
+            Some(checkpoint) => args
+                .validate()
+                .and_then(|()| args.validate_resume(checkpoint)),
+            None => args.validate(),
+        };
+        if let Err(message) = validation {
+            Self::command()
+                .error(ErrorKind::ValueValidation, message)
+                .exit();
+        }
+        (args, checkpoint)
+    }
+
+    /// `--resume` alone. Other options may depend on options saved in the
+    /// checkpoint, so they are only checked once those are put in front.
+    fn resume_path(command_line: &[String]) -> Option<PathBuf> {
+        let program = "ray-tracing-weekend".to_string();
+        Self::command()
+            .ignore_errors(true)
+            .try_get_matches_from(std::iter::once(&program).chain(command_line))
+            .ok()?
+            .get_one::<PathBuf>("resume")
+            .cloned()
+    }
+
+    /// Where progress is saved, if anywhere
+    pub(crate) fn checkpoint_path(&self) -> Option<&Path> {
+        self.checkpoint.as_deref().or(self.resume.as_deref())
+    }
+
+    pub(crate) fn camera_settings(&self) -> CameraSettings {
+        CameraSettings {
+            look_from: self.look_from,
+            look_at: self.look_at,
+            view_up: self.view_up,
+            vertical_fov: self.vertical_fov,
+            aperture: self.aperture,
+            dist_to_focus: self.dist_to_focus,
+            shutter_open: self.shutter_open,
+            shutter_close: self.shutter_close,
+        }
+    }
+
+    pub(crate) fn adaptive(&self) -> Option<Adaptive> {
+        self.adaptive_threshold.map(|threshold| Adaptive {
+            threshold,
+            min_samples: self.min_samples,
+        })
+    }
+
+    pub(crate) fn denoiser(&self) -> Option<Denoiser> {
+        self.denoise.then_some(Denoiser {
+            iterations: self.denoise_iterations,
+            strength: self.denoise_strength,
+        })
+    }
+
+    /// Whether the renderer has to record what camera rays hit first
+    pub(crate) fn first_hits(&self) -> bool {
+        self.denoise || !self.aovs.is_empty() || self.aov_exr.is_some()
+    }
+
+    pub(crate) fn bounces(&self) -> Bounces {
+        Bounces {
+            max: self.max_depth,
+            roulette_after: self.rr_depth,
+        }
+    }
+
+    pub(crate) fn output_settings(&self) -> OutputSettings {
+        OutputSettings {
+            tone_mapping: ToneMapping {
+                operator: self.tone_map,
+                exposure: self.exposure,
+                white_point: self.white_point,
+            },
+            exr_precision: self.exr_precision,
+            exr_compression: self.exr_compression,
+        }
+    }
+
+    pub(crate) fn image_height(&self) -> usize {
+        (self.width as f64 / self.aspect_ratio) as usize
+    }
+
+    /// Settings that would not fit the saved progress
+    fn validate_resume(&self, checkpoint: &Checkpoint) -> Result<(), String> {
+        let framebuffer = &checkpoint.framebuffer;
+        if (framebuffer.width, framebuffer.height) != (self.width, self.image_height()) {
+            return Err(format!(
+                "the checkpoint is {}x{} pixels, but the settings make {}x{}",
+                framebuffer.width,
+                framebuffer.height,
+                self.width,
+                self.image_height()
+            ));
+        }
+        if self.first_hits() && !checkpoint.first_hits {
+            return Err(
+                "the checkpoint has no first hits for --denoise and AOVs, they have to be \
+                 requested when the render starts"
+                    .to_string(),
+            );
+        }
+
+        // New samples are added to the saved ones, so they have to come from
+        // the same scene, camera and sampler
+        let program = "ray-tracing-weekend";
+        let saved = Self::try_parse_from(
+            std::iter::once(program).chain(checkpoint.args.iter().map(String::as_str)),
+        )
+        .map_err(|_| "the checkpoint's settings are not valid".to_string())?;
+        for ((option, saved), (_, current)) in
+            saved.image_settings().iter().zip(self.image_settings())
+        {
+            if *saved != current {
+                return Err(format!(
+                    "{} cannot change when resuming, the checkpoint was rendered with {}",
+                    option, saved
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Options the image depends on, besides how many samples it takes, as
+    /// option names and values
+    fn image_settings(&self) -> Vec<(&'static str, String)> {
+        fn optional(value: Option<impl fmt::Debug>) -> String {
+            value.map_or_else(|| "none".to_string(), |value| format!("{:?}", value))
+        }
+        vec![
+            ("--scene", optional(self.scene.as_ref())),
+            ("--seed", optional(self.seed.as_ref())),
+            ("--aspect-ratio", self.aspect_ratio.to_string()),
+            ("--look-from", format!("{:?}", self.look_from)),
+            ("--look-at", format!("{:?}", self.look_at)),
+            ("--view-up", format!("{:?}", self.view_up)),
+            ("--vertical-fov", self.vertical_fov.to_string()),
+            ("--aperture", self.aperture.to_string()),
+            ("--dist-to-focus", self.dist_to_focus.to_string()),
+            ("--shutter-open", self.shutter_open.to_string()),
+            ("--shutter-close", self.shutter_close.to_string()),
+            ("--integrator", format!("{:?}", self.integrator)),
+            ("--mis-heuristic", format!("{:?}", self.mis_heuristic)),
+            ("--max-depth", self.max_depth.to_string()),
+            ("--rr-depth", self.rr_depth.to_string()),
+            ("--background", optional(self.background.as_ref())),
+            ("--gradient", optional(self.gradient.as_ref())),
+            ("--environment", optional(self.environment.as_ref())),
+            (
+                "--environment-rotation",
+                self.environment_rotation.to_string(),
+            ),
+            (
+                "--environment-intensity",
+                self.environment_intensity.to_string(),
+            ),
+        ]
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let aov_paths = self.aovs.iter().map(|(_, path)| path);
+        for path in std::iter::once(&self.output)
+            .chain(&self.sample_heatmap)
+            .chain(aov_paths)
+        {
+            if let Err(err) = ImageFormat::from_path(path) {
+                return Err(err.to_string());
+            }
+        }
+        if !self.exposure.is_finite() {
+            return Err("--exposure must be finite".to_string());
+        }
+        if !(self.white_point > 0.0 && self.white_point.is_finite()) {
+            return Err(format!(
+                "--white-point must be positive, got {}",
+                self.white_point
+            ));
+        }
+        if let Some(path) = &self.aov_exr {
+            if ImageFormat::from_path(path).ok() != Some(ImageFormat::Exr) {
+                return Err(format!(
+                    "--aov-exr needs an .exr file, got '{}'",
+                    path.display()
+                ));
+            }
+        }
+        if self.width < 2 || self.image_height() < 2 {
+            return Err(format!(
+                "the image must be at least 2x2 pixels, got {}x{}",
+                self.width,
+                self.image_height()
+            ));
+        }
+        if self.samples == 0 {
+            return Err("--samples must be at least 1".to_string());
+        }
+        if let Some(threshold) = self.adaptive_threshold {
+            if !(threshold > 0.0 && threshold.is_finite()) {
+                return Err(format!(
+                    "--adaptive-threshold must be positive, got {}",
+                    threshold
+                ));
+            }
+            if self.min_samples == 0 || self.min_samples > self.samples {
+                return Err(format!(
+                    "--min-samples must be between 1 and --samples ({}), got {}",
+                    self.samples, self.min_samples
+                ));
+            }
+        }
+        if self.denoise {
+            // The denoiser tells noise from detail by the variance of pixels
+            let fewest = match self.adaptive_threshold {
+                Some(_) => self.min_samples,
+                None => self.samples,
+            };
+            if fewest < 2 {
+                return Err(
+                    "--denoise needs at least 2 samples per pixel to estimate the noise"
+                        .to_string(),
+                );
+            }
+            if !(1..=10).contains(&self.denoise_iterations) {
+                return Err(format!(
+                    "--denoise-iterations must be between 1 and 10, got {}",
+                    self.denoise_iterations
+                ));
+            }
+            if !(self.denoise_strength > 0.0 && self.denoise_strength.is_finite()) {
+                return Err(format!(
+                    "--denoise-strength must be positive, got {}",
+                    self.denoise_strength
+                ));
+            }
+        }
+        if self.max_depth == 0 {
+            return Err("--max-depth must be at least 1".to_string());
+        }
+        self.camera_settings().validate()?;
+        let colors = self
+            .background
+            .iter()
+            .chain(self.gradient.iter().flat_map(|(bottom, top)| [bottom, top]));
+        for color in colors {
+            if color.x() < 0.0 || color.y() < 0.0 || color.z() < 0.0 {
+                return Err("background colors must not have negative components".to_string());
+            }
+        }
+        if !self.environment_rotation.is_finite() {
+            return Err("--environment-rotation must be finite".to_string());
+        }
+        if !(self.environment_intensity >= 0.0 && self.environment_intensity.is_finite()) {
+            return Err(format!(
+                "--environment-intensity must not be negative, got {}",
+                self.environment_intensity
+            ));
+        }
+        if !(self.checkpoint_interval > 0.0 && self.checkpoint_interval.is_finite()) {
+            return Err(format!(
+                "--checkpoint-interval must be positive, got {}",
+                self.checkpoint_interval
+            ));
+        }
+        if self.threads == Some(0) {
+            return Err("--threads must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn parse_vec3(value: &str) -> Result<Vec3, String> {
+    let components = value
+        .split(',')
+        .map(|component| component.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| format!("invalid number in '{}': {}", value, err))?;
+
+    match components[..] {
+        [x, y, z] if x.is_finite() && y.is_finite() && z.is_finite() => Ok(Vec3::new([x, y, z])),
+        [_, _, _] => Err(format!("'{}' contains a non-finite component", value)),
+        _ => Err(format!(
+            "expected three components as `x,y,z`, got '{}'",
+            value
+        )),
+    }
+}
+
+fn parse_aov_output(value: &str) -> Result<(Aov, PathBuf), String> {
+    match value.split_once('=') {
+        Some((kind, path)) if !path.is_empty() => {
+            Ok((Aov::from_str(kind.trim(), true)?, PathBuf::from(path)))
+        }
+        _ => Err(format!("expected `kind=path`, got '{}'", value)),
+    }
+}
+
+fn parse_gradient(value: &str) -> Result<(Color, Color), String> {
+    match value.split_once(':') {
+        Some((bottom, top)) => Ok((parse_vec3(bottom)?, parse_vec3(top)?)),
+        None => Err(format!(
+            "expected bottom and top colors as `r,g,b:r,g,b`, got '{}'",
+            value
+        )),
+    }
+}
+
+fn parse_aspect_ratio(value: &str) -> Result<f64, String> {
+    let ratio = match value.split_once(':') {
+        Some((width, height)) => {
+            let width = width.trim().parse::<f64>().map_err(|err| err.to_string())?;
+            let height = height
+                .trim()
+                .parse::<f64>()
+                .map_err(|err| err.to_string())?;
+            width / height
+        }
+        None => value.trim().parse::<f64>().map_err(|err| err.to_string())?,
+    };
+
+    if ratio > 0.0 && ratio.is_finite() {
+        Ok(ratio)
+    } else {
+        Err(format!("'{}' is not a positive aspect ratio", value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_aov_output, parse_aspect_ratio, parse_gradient, parse_vec3, Args};
+    use crate::{aov::Aov, checkpoint::Checkpoint, render::Framebuffer, vec3::Vec3};
+    use clap::Parser;
+
+    fn args(extra: &[&str]) -> Args {
+        Args::try_parse_from(std::iter::once("ray-tracing-weekend").chain(extra.iter().copied()))
+            .unwrap()
+    }
+
+    #[test]
+    fn vec3() {
+        assert_eq!(parse_vec3("1, -2,3.5"), Ok(Vec3::new([1.0, -2.0, 3.5])));
+        assert!(parse_vec3("1,2").is_err());
+        assert!(parse_vec3("1,2,x").is_err());
+        assert!(parse_vec3("1,2,inf").is_err());
+    }
+
+    #[test]
+    fn gradient() {
+        assert_eq!(
+            parse_gradient("1,1,1:0.5,0.7,1"),
+            Ok((Vec3::new([1.0, 1.0, 1.0]), Vec3::new([0.5, 0.7, 1.0])))
+        );
+        assert!(parse_gradient("1,1,1").is_err());
+    }
+
+    #[test]
+    fn background_options_conflict() {
+        let result = Args::try_parse_from([
+            "ray-tracing-weekend",
+            "--background",
+            "0,0,0",
+            "--environment",
+            "sky.hdr",
+        ]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn aov_output() {
+        assert_eq!(
+            parse_aov_output("normal=out/normal.exr"),
+            Ok((Aov::Normal, "out/normal.exr".into()))
+        );
+        assert!(parse_aov_output("normal.exr").is_err());
+        assert!(parse_aov_output("depth=").is_err());
+        assert!(parse_aov_output("color=color.png").is_err());
+
+        let args = args(&["--aov", "albedo=albedo.pfm", "--aov", "depth=depth.png"]);
+        assert_eq!(args.aovs.len(), 2);
+        assert!(args.first_hits());
+    }
+
+    #[test]
+    fn later_options_win() {
+        // Resuming puts the new options after the saved ones
+        let args = args(&[
+            "--samples",
+            "16",
+            "--aov",
+            "depth=depth.png",
+            "--samples",
+            "64",
+        ]);
+        assert_eq!(args.samples, 64);
+        assert_eq!(args.aovs.len(), 1);
+    }
+
+    #[test]
+    fn resume_needs_only_merged_options() {
+        let command_line: Vec<String> =
+            ["--min-samples", "3", "--resume", "a.ckpt", "--samples", "x"]
+                .iter()
+                .map(|arg| arg.to_string())
+                .collect();
+        assert_eq!(Args::resume_path(&command_line), Some("a.ckpt".into()));
+        assert_eq!(Args::resume_path(&command_line[..2]), None);
+
+        // Options that require others are fine once the saved ones come first
+        let args = args(&[
+            "--adaptive-threshold",
+            "0.01",
+            "--denoise",
+            "--resume",
+            "a.ckpt",
+            "--min-samples",
+            "3",
+            "--denoise-strength",
+            "2",
+        ]);
+        assert_eq!(args.min_samples, 3);
+        assert_eq!(args.denoise_strength, 2.0);
+    }
+
+    #[test]
+    fn resume_keeps_the_image_settings() {
+        let saved = ["--width", "12", "--scene", "a.toml", "--seed", "5"];
+        let checkpoint = Checkpoint {
+            args: saved.iter().map(|arg| arg.to_string()).collect(),
+            first_hits: false,
+            framebuffer: Framebuffer::new(12, 8),
+        };
+        let resumed = |extra: &[&str]| {
+            let mut command_line = saved.to_vec();
+            command_line.extend(["--resume", "a.ckpt"]);
+            command_line.extend(extra);
+            args(&command_line).validate_resume(&checkpoint)
+        };
+
+        assert_eq!(resumed(&[]), Ok(()));
+        assert_eq!(
+            resumed(&["--samples", "64", "--tone-map", "aces", "-o", "b.exr"]),
+            Ok(())
+        );
+        for changed in [
+            &["--seed", "6"][..],
+            &["--scene", "b.toml"],
+            &["--integrator", "path"],
+            &["--max-depth", "4"],
+        ] {
+            let err = resumed(changed).unwrap_err();
+            assert!(err.starts_with(changed[0]), "{}", err);
+        }
+    }
+
+    #[test]
+    fn aspect_ratio() {
+        assert_eq!(parse_aspect_ratio("3:2"), Ok(1.5));
+        assert_eq!(parse_aspect_ratio("1.5"), Ok(1.5));
+        assert!(parse_aspect_ratio("16:0").is_err());
+        assert!(parse_aspect_ratio("-1").is_err());
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        let args = args(&[]);
+        assert_eq!(args.validate(), Ok(()));
+        assert_eq!(args.image_height(), 800);
+    }
+
+    #[test]
+    fn rejects_degenerate_settings() {
+        assert!(args(&["--samples", "0"]).validate().is_err());
+        assert!(args(&["--adaptive-threshold", "0"]).validate().is_err());
+        assert!(
+            args(&["--adaptive-threshold", "0.01", "--min-samples", "20"])
+                .validate()
+                .is_err()
+        );
+        assert!(args(&[
+            "--adaptive-threshold",
+            "0.01",
+            "--sample-heatmap",
+            "heat.gif"
+        ])
+        .validate()
+        .is_err());
+        assert!(args(&["--max-depth", "0"]).validate().is_err());
+        assert!(args(&["--look-from", "0,0,0"]).validate().is_err());
+        assert!(args(&["--view-up", "13,2,3"]).validate().is_err());
+        assert!(args(&["--vertical-fov", "180"]).validate().is_err());
+        assert!(args(&["--aperture", "-1"]).validate().is_err());
+        assert!(args(&["--width", "1"]).validate().is_err());
+        assert!(args(&["--threads", "0"]).validate().is_err());
+        assert!(args(&["--shutter-open", "1"]).validate().is_err());
+        assert!(args(&["--background", "-1,0,0"]).validate().is_err());
+        assert!(args(&["--gradient", "1,1,1:0,-1,0"]).validate().is_err());
+        assert!(
+            args(&["--environment", "sky.hdr", "--environment-intensity", "-1"])
+                .validate()
+                .is_err()
+        );
+        assert!(args(&["--output", "image.gif"]).validate().is_err());
+        assert!(args(&["--exposure", "inf"]).validate().is_err());
+        assert!(args(&["--white-point", "0"]).validate().is_err());
+        assert!(args(&["--aov", "depth=depth.gif"]).validate().is_err());
+        assert!(args(&["--aov-exr", "layers.pfm"]).validate().is_err());
+        assert!(args(&["--checkpoint-interval", "0"]).validate().is_err());
+        assert!(args(&["--denoise", "--samples", "1"]).validate().is_err());
+        assert!(args(&["--denoise", "--denoise-strength", "0"])
+            .validate()
+            .is_err());
+        assert!(args(&["--denoise", "--denoise-iterations", "0"])
+            .validate()
+            .is_err());
+    }
+}
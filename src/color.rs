@@ -9,14 +9,24 @@ macro_rules! color {
 }
 
 impl Color {
-    pub(crate) fn write(&self, samples_per_pixel: u32) -> String {
-        let scale = 1.0 / samples_per_pixel as f64;
-        format!(
-            "{} {} {} ",
-            (256.0 * (self.x() * scale).sqrt().clamp(0.0, 0.999)) as u8,
-            (256.0 * (self.y() * scale).sqrt().clamp(0.0, 0.999)) as u8,
-            (256.0 * (self.z() * scale).sqrt().clamp(0.0, 0.999)) as u8,
-        )
+    /// 8-bit sRGB of a linear color between 0 and 1, usually the output of
+    /// a `ToneMapping`
+    pub(crate) fn to_rgb8(self) -> [u8; 3] {
+        [0, 1, 2].map(|c| (255.0 * srgb_encode(self[c].clamp(0.0, 1.0))).round() as u8)
+    }
+
+    /// Perceived brightness of a linear Rec. 709 color
+    pub(crate) fn luminance(&self) -> f64 {
+        0.2126 * self.x() + 0.7152 * self.y() + 0.0722 * self.z()
+    }
+}
+
+/// The sRGB transfer function, linear near black and a 2.4 power above
+fn srgb_encode(linear: f64) -> f64 {
+    if linear <= 0.003_130_8 {
+        12.92 * linear
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
     }
 }
 
@@ -31,3 +41,15 @@ impl fmt::Display for Color {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Color;
+
+    #[test]
+    fn srgb() {
+        assert_eq!(Color::new([0.0, 1.0, 2.0]).to_rgb8(), [0, 255, 255]);
+        // Middle grey ends up a little below half way
+        assert_eq!(Color::new([0.18, 0.5, 0.002]).to_rgb8(), [118, 188, 7]);
+    }
+}
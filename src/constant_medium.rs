@@ -0,0 +1,136 @@
+use crate::{
+    aabb::Aabb,
+    hittable::{HitRecord, Hittable},
+    material::Material,
+    ray::Ray,
+    utils::{self, Rng},
+    vec3::Vec3,
+};
+use std::sync::Arc;
+
+/// Fog or smoke of the same density everywhere inside a closed boundary.
+///
+/// Rays are scattered at a random distance along their path through the
+/// volume, usually by an `Isotropic` material. The boundary must be convex,
+/// a ray is assumed to enter and leave it once.
+pub(crate) struct ConstantMedium {
+    boundary: Box<dyn Hittable>,
+    neg_inv_density: f64,
+    phase_function: Arc<dyn Material>,
+}
+
+impl ConstantMedium {
+    pub(crate) fn new(
+        boundary: Box<dyn Hittable>,
+        density: f64,
+        phase_function: Arc<dyn Material>,
+    ) -> Self {
+        assert!(density > 0.0, "a medium needs a positive density");
+        Self {
+            boundary,
+            neg_inv_density: -1.0 / density,
+            phase_function,
+        }
+    }
+}
+
+impl Hittable for ConstantMedium {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut Rng) -> Option<HitRecord<'_>> {
+        // Where the ray's line enters and leaves the boundary, even behind
+        // the origin, so rays starting inside the volume work too
+        let entry = self
+            .boundary
+            .hit(ray, f64::NEG_INFINITY, f64::INFINITY, rng)?
+            .t;
+        let exit = self
+            .boundary
+            .hit(ray, entry + 0.0001, f64::INFINITY, rng)?
+            .t;
+
+        let entry = entry.max(t_min).max(0.0);
+        let exit = exit.min(t_max);
+        if entry >= exit {
+            return None;
+        }
+
+        let ray_length = ray.direction.len();
+        let distance_inside = (exit - entry) * ray_length;
+        let hit_distance = self.neg_inv_density * utils::random_float(rng).ln();
+        if hit_distance > distance_inside {
+            return None;
+        }
+
+        // Normal and side are meaningless inside a volume
+        let t = entry + hit_distance / ray_length;
+        Some(HitRecord::new(
+            ray.at(t),
+            Vec3::new([1.0, 0.0, 0.0]),
+            t,
+            (0.0, 0.0),
+            true,
+            self.phase_function.as_ref(),
+        ))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.boundary.bounding_box()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ConstantMedium;
+    use crate::{
+        color::Color, hittable::Hittable, material::Isotropic, point::Point, ray::Ray,
+        sphere::Sphere, texture::SolidColor, utils::Rng, vec3::Vec3,
+    };
+    use rand::SeedableRng;
+    use std::sync::Arc;
+
+    fn fog(density: f64) -> ConstantMedium {
+        let albedo = Arc::new(SolidColor::new(Color::new([0.5, 0.5, 0.5])));
+        let material = Arc::new(Isotropic::with_texture(albedo));
+        let boundary = Sphere::new(Point::new([0.0, 0.0, 0.0]), 1.0, material.clone());
+        ConstantMedium::new(Box::new(boundary), density, material)
+    }
+
+    #[test]
+    fn scatter_probability() {
+        let mut rng = Rng::seed_from_u64(0);
+        let medium = fog(1.0);
+        let ray = Ray::new(
+            Point::new([0.0, 0.0, -5.0]),
+            Vec3::new([0.0, 0.0, 2.0]),
+            0.0,
+        );
+
+        // Two units of fog with density 1 stop a ray with probability 1 - e^-2
+        let runs = 20_000;
+        let mut hits = 0;
+        for _ in 0..runs {
+            if let Some(hit) = medium.hit(&ray, 0.001, f64::INFINITY, &mut rng) {
+                assert!((2.0..=3.0).contains(&hit.t));
+                hits += 1;
+            }
+        }
+        let expected = 1.0 - (-2.0f64).exp();
+        assert!((hits as f64 / runs as f64 - expected).abs() < 0.01);
+    }
+
+    #[test]
+    fn dense_fog_from_inside() {
+        let mut rng = Rng::seed_from_u64(0);
+        let medium = fog(1e6);
+        let ray = Ray::new(Point::new([0.0, 0.0, 0.0]), Vec3::new([1.0, 0.0, 0.0]), 0.0);
+        let hit = medium.hit(&ray, 0.001, f64::INFINITY, &mut rng).unwrap();
+        assert!(hit.t < 0.01);
+
+        // Nothing to hit once the ray stops before the volume
+        let outside = Ray::new(
+            Point::new([-5.0, 0.0, 0.0]),
+            Vec3::new([1.0, 0.0, 0.0]),
+            0.0,
+        );
+        assert!(medium.hit(&outside, 0.001, 3.0, &mut rng).is_none());
+    }
+}
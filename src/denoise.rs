@@ -0,0 +1,321 @@
+use crate::{aov::FirstHit, color::Color, vec3::Vec3};
+use rayon::prelude::*;
+
+/// Edge-avoiding à-trous wavelet filter, the spatial part of SVGF
+/// (Schied et al. 2017).
+///
+/// Every iteration blurs with a 5x5 kernel whose taps are twice as far apart
+/// as in the previous one. Taps are weighted down where the first hits
+/// differ in normal or depth, or where the brightness differs by more than
+/// the pixel's noise explains. The lighting is filtered on its own, divided
+/// by the albedo, so textures stay sharp.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Denoiser {
+    pub(crate) iterations: u32,
+    /// Scales how much brightness difference counts as noise, higher values
+    /// blur more
+    pub(crate) strength: f64,
+}
+
+/// B3 spline, the weights of the taps in either direction
+const KERNEL: [f64; 3] = [3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+/// Exponent of the normal weight, `(n_p . n_q)^NORMAL_POWER`
+const NORMAL_POWER: i32 = 128;
+
+/// One iteration of the filter over an image
+struct Pass<'a> {
+    width: usize,
+    height: usize,
+    /// Distance between the taps of the kernel
+    step: usize,
+    samples: &'a [Sample],
+    deviations: Vec<f64>,
+    first_hits: &'a [FirstHit],
+    gradients: &'a [f64],
+}
+
+/// Lighting of a pixel with the albedo divided out, and what is known about
+/// its noise
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    irradiance: Color,
+    /// Variance of the luminance of `irradiance`
+    variance: f64,
+}
+
+impl Denoiser {
+    /// Filters the average `colors` of an image `width` pixels wide, with
+    /// the variance of their luminance and their averaged first hits
+    pub(crate) fn apply(
+        &self,
+        width: usize,
+        colors: &[Color],
+        variances: &[f64],
+        first_hits: &[FirstHit],
+    ) -> Vec<Color> {
+        let height = colors.len() / width;
+        // Channels without albedo stay as they are
+        let albedos: Vec<Color> = first_hits
+            .iter()
+            .map(|first_hit| first_hit.albedo.map(|c| if c > 1e-3 { c } else { 1.0 }))
+            .collect();
+        let mut samples: Vec<Sample> = colors
+            .iter()
+            .zip(variances)
+            .zip(&albedos)
+            .map(|((color, &variance), albedo)| Sample {
+                irradiance: Color::new([0, 1, 2].map(|c| color[c] / albedo[c])),
+                variance: variance / albedo.luminance().powi(2),
+            })
+            .collect();
+        let gradients = depth_gradients(width, height, first_hits);
+
+        for iteration in 0..self.iterations {
+            let step = 1 << iteration;
+            let pass = Pass {
+                width,
+                height,
+                step,
+                deviations: blurred_deviations(width, height, &samples),
+                samples: &samples,
+                first_hits,
+                gradients: &gradients,
+            };
+            let mut filtered = samples.clone();
+            filtered
+                .par_chunks_mut(width)
+                .enumerate()
+                .for_each(|(y, row)| {
+                    for (x, sample) in row.iter_mut().enumerate() {
+                        *sample = self.filter_pixel(&pass, x, y);
+                    }
+                });
+            samples = filtered;
+        }
+
+        samples
+            .iter()
+            .zip(&albedos)
+            .map(|(sample, albedo)| &sample.irradiance * albedo)
+            .collect()
+    }
+
+    fn filter_pixel(&self, pass: &Pass<'_>, x: usize, y: usize) -> Sample {
+        let Pass {
+            width,
+            height,
+            step,
+            samples,
+            first_hits,
+            gradients,
+            ..
+        } = *pass;
+        let p = y * width + x;
+        let luminance = samples[p].irradiance.luminance();
+        // SVGF's sigma_l is 4, noise should not stop the filter
+        let luminance_scale = 4.0 * self.strength * pass.deviations[p] + 1e-10;
+        let normal = first_hits[p].normal;
+        let depth = first_hits[p].depth;
+
+        let mut weights = 0.0;
+        let mut irradiance = Color::new([0.0, 0.0, 0.0]);
+        let mut variance = 0.0;
+        for dy in -2i64..=2 {
+            for dx in -2i64..=2 {
+                let qx = x as i64 + dx * step as i64;
+                let qy = y as i64 + dy * step as i64;
+                if qx < 0 || qy < 0 || qx >= width as i64 || qy >= height as i64 {
+                    continue;
+                }
+                let q = qy as usize * width + qx as usize;
+                let kernel =
+                    KERNEL[dx.unsigned_abs() as usize] * KERNEL[dy.unsigned_abs() as usize];
+
+                let weight = if q == p {
+                    kernel
+                } else {
+                    let luminance_weight =
+                        -(luminance - samples[q].irradiance.luminance()).abs() / luminance_scale;
+                    let distance = ((dx * dx + dy * dy) as f64).sqrt() * step as f64;
+                    let depth_weight =
+                        -(depth - first_hits[q].depth).abs() / (gradients[p] * distance + 1e-10);
+                    let normal_weight = normal_similarity(&normal, &first_hits[q]);
+                    kernel * normal_weight * (luminance_weight + depth_weight).exp()
+                };
+                weights += weight;
+                irradiance = irradiance + weight * &samples[q].irradiance;
+                variance += weight * weight * samples[q].variance;
+            }
+        }
+
+        // The pixel itself always counts, so the weights are never zero
+        Sample {
+            irradiance: irradiance / weights,
+            variance: variance / (weights * weights),
+        }
+    }
+}
+
+/// `(n_p . n_q)^NORMAL_POWER` of the directions of the averaged normals.
+/// Pixels where rays left the scene only match each other.
+fn normal_similarity(normal: &Vec3, other: &FirstHit) -> f64 {
+    let other = other.normal;
+    match (normal.near_zero(), other.near_zero()) {
+        (true, true) => 1.0,
+        (false, false) => {
+            let cosine = normal.dot(&other) / (normal.len() * other.len());
+            cosine.max(0.0).powi(NORMAL_POWER)
+        }
+        _ => 0.0,
+    }
+}
+
+/// How much the depth changes from one pixel to the next, the larger of the
+/// central differences along x and y. Steep surfaces must not stop the
+/// filter just because they recede.
+fn depth_gradients(width: usize, height: usize, first_hits: &[FirstHit]) -> Vec<f64> {
+    let depth = |x: usize, y: usize| first_hits[y * width + x].depth;
+    let mut gradients = Vec::with_capacity(first_hits.len());
+    for y in 0..height {
+        for x in 0..width {
+            let along_x = depth(x.saturating_sub(1), y) - depth((x + 1).min(width - 1), y);
+            let along_y = depth(x, y.saturating_sub(1)) - depth(x, (y + 1).min(height - 1));
+            gradients.push(0.5 * along_x.abs().max(along_y.abs()));
+        }
+    }
+    gradients
+}
+
+/// Standard deviation of every pixel's luminance, blurred with a 3x3
+/// Gaussian, which is steadier than the pixel's own estimate
+fn blurred_deviations(width: usize, height: usize, samples: &[Sample]) -> Vec<f64> {
+    const GAUSSIAN: [f64; 2] = [1.0 / 2.0, 1.0 / 4.0];
+    let mut deviations = Vec::with_capacity(samples.len());
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = 0.0;
+            let mut weights = 0.0;
+            for dy in -1i64..=1 {
+                for dx in -1i64..=1 {
+                    let qx = x as i64 + dx;
+                    let qy = y as i64 + dy;
+                    if qx < 0 || qy < 0 || qx >= width as i64 || qy >= height as i64 {
+                        continue;
+                    }
+                    let weight =
+                        GAUSSIAN[dx.unsigned_abs() as usize] * GAUSSIAN[dy.unsigned_abs() as usize];
+                    sum += weight * samples[qy as usize * width + qx as usize].variance;
+                    weights += weight;
+                }
+            }
+            deviations.push((sum / weights).sqrt());
+        }
+    }
+    deviations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Denoiser;
+    use crate::{aov::FirstHit, color::Color, utils::Rng, vec3::Vec3};
+    use rand::{Rng as _, SeedableRng};
+
+    /// A grey wall on the left, facing the camera, and a red wall on the
+    /// right, turned away from it, both evenly lit but noisy
+    fn two_walls(rng: &mut Rng) -> (Vec<Color>, Vec<f64>, Vec<FirstHit>) {
+        let (width, height) = (16, 8);
+        let mut colors = vec![];
+        let mut variances = vec![];
+        let mut first_hits = vec![];
+        for _y in 0..height {
+            for x in 0..width {
+                let (albedo, normal) = if x < width / 2 {
+                    (Color::new([0.5, 0.5, 0.5]), Vec3::new([0.0, 0.0, 1.0]))
+                } else {
+                    (Color::new([0.8, 0.1, 0.1]), Vec3::new([1.0, 0.0, 0.0]))
+                };
+                let lighting = if x < width / 2 { 1.0 } else { 0.25 };
+                let noise = rng.gen_range(0.5..1.5);
+                colors.push(noise * lighting * &albedo);
+                variances.push(0.08 * (lighting * albedo.luminance()).powi(2));
+                first_hits.push(FirstHit {
+                    albedo,
+                    normal,
+                    depth: 5.0,
+                    ..FirstHit::zero()
+                });
+            }
+        }
+        (colors, variances, first_hits)
+    }
+
+    #[test]
+    fn smooths_noise_but_not_edges() {
+        let mut rng = Rng::seed_from_u64(0);
+        let (colors, variances, first_hits) = two_walls(&mut rng);
+        let denoiser = Denoiser {
+            iterations: 4,
+            strength: 1.0,
+        };
+        let denoised = denoiser.apply(16, &colors, &variances, &first_hits);
+
+        let spread = |colors: &[Color], range: std::ops::Range<usize>| {
+            let values: Vec<f64> = colors
+                .chunks(16)
+                .flat_map(|row| row[range.clone()].iter().map(|color| color.x()))
+                .collect();
+            let mean = values.iter().sum::<f64>() / values.len() as f64;
+            let deviation = values.iter().map(|v| (v - mean).abs()).sum::<f64>();
+            (mean, deviation / values.len() as f64)
+        };
+        for (range, expected) in [(0..8, 0.5), (8..16, 0.2)] {
+            let (_, noisy) = spread(&colors, range.clone());
+            let (mean, smooth) = spread(&denoised, range);
+            assert!(smooth < 0.2 * noisy, "{} {}", smooth, noisy);
+            // Neither wall bleeds into the other
+            assert!((mean - expected).abs() < 0.05 * expected, "{}", mean);
+        }
+        // Red stays red
+        assert!(denoised
+            .iter()
+            .skip(8)
+            .step_by(16)
+            .all(|c| c.x() > 4.0 * c.y()));
+    }
+
+    #[test]
+    fn background() {
+        // Rays leaving the scene have no normal or depth
+        let colors = vec![Color::new([0.5, 0.7, 1.0]); 12];
+        let first_hits = vec![
+            FirstHit {
+                albedo: Color::new([1.0, 1.0, 1.0]),
+                ..FirstHit::zero()
+            };
+            12
+        ];
+        let denoiser = Denoiser {
+            iterations: 2,
+            strength: 1.0,
+        };
+        let denoised = denoiser.apply(4, &colors, &[0.0; 12], &first_hits);
+        for (a, b) in denoised.iter().zip(&colors) {
+            assert!((*a - *b).len() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn zero_iterations() {
+        let mut rng = Rng::seed_from_u64(0);
+        let (colors, variances, first_hits) = two_walls(&mut rng);
+        let denoiser = Denoiser {
+            iterations: 0,
+            strength: 1.0,
+        };
+        let unchanged = denoiser.apply(16, &colors, &variances, &first_hits);
+        for (a, b) in unchanged.iter().zip(&colors) {
+            assert!((*a - *b).len() < 1e-12);
+        }
+    }
+}
@@ -1,33 +1,83 @@
-use std::vec;
+use crate::{
+    aabb::Aabb,
+    material::Material,
+    point::Point,
+    ray::Ray,
+    utils::{self, Rng},
+    vec3::Vec3,
+};
+use std::sync::Arc;
 
-use crate::{material::Material, point::Point, ray::Ray, vec3::Vec3};
+pub(crate) trait Hittable: Send + Sync {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut Rng) -> Option<HitRecord<'_>>;
 
-pub(crate) trait Hittable {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+    /// Returns `None` for objects without a finite extent
+    fn bounding_box(&self) -> Option<Aabb>;
+
+    /// Density, with respect to solid angle, of `random` picking `direction`
+    /// from `origin`. Objects that cannot be sampled keep the zero default
+    /// and must not be used as lights.
+    fn pdf_value(&self, _origin: &Point, _direction: &Vec3, _rng: &mut Rng) -> f64 {
+        0.0
+    }
+
+    /// Direction from `origin` towards a random point of the object
+    fn random(&self, _origin: &Point, _rng: &mut Rng) -> Vec3 {
+        Vec3::new([1.0, 0.0, 0.0])
+    }
 }
 
-pub(crate) struct HitRecord {
+/// Shared objects, e.g. a model placed several times with `Transformed`
+impl<T: Hittable + ?Sized> Hittable for Arc<T> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut Rng) -> Option<HitRecord<'_>> {
+        self.as_ref().hit(ray, t_min, t_max, rng)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.as_ref().bounding_box()
+    }
+
+    fn pdf_value(&self, origin: &Point, direction: &Vec3, rng: &mut Rng) -> f64 {
+        self.as_ref().pdf_value(origin, direction, rng)
+    }
+
+    fn random(&self, origin: &Point, rng: &mut Rng) -> Vec3 {
+        self.as_ref().random(origin, rng)
+    }
+}
+
+pub(crate) struct HitRecord<'a> {
     pub(crate) point: Point,
     pub(crate) normal: Vec3,
     pub(crate) t: f64,
+    /// Surface coordinates of the hit point, used for texture lookups
+    pub(crate) u: f64,
+    pub(crate) v: f64,
     pub(crate) front_face: bool,
-    pub(crate) material: Box<dyn Material>,
+    pub(crate) material: &'a dyn Material,
+    /// Which object of the scene was hit, counting from 1. Zero for objects
+    /// that are not `Numbered`.
+    pub(crate) object_id: u32,
 }
 
-impl HitRecord {
+impl<'a> HitRecord<'a> {
     pub(crate) fn new(
         point: Point,
         normal: Vec3,
         t: f64,
+        (u, v): (f64, f64),
         front_face: bool,
-        material: Box<dyn Material>,
+        material: &'a dyn Material,
     ) -> Self {
         Self {
             point,
             normal,
             t,
+            u,
+            v,
             front_face,
             material,
+            object_id: 0,
         }
     }
 
@@ -57,15 +107,60 @@ impl HittableList {
     pub(crate) fn add(&mut self, object: Box<dyn Hittable>) {
         self.objects.push(object);
     }
+
+    pub(crate) fn into_objects(self) -> Vec<Box<dyn Hittable>> {
+        self.objects
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    /// Gives every object its position in the list, from 1, as object ID
+    pub(crate) fn numbered(self) -> Self {
+        let objects = self
+            .objects
+            .into_iter()
+            .zip(1..)
+            .map(|(object, id)| Box::new(Numbered { object, id }) as Box<dyn Hittable>)
+            .collect();
+        Self { objects }
+    }
+}
+
+/// Marks the hit records of an object with its ID
+struct Numbered {
+    object: Box<dyn Hittable>,
+    id: u32,
+}
+
+impl Hittable for Numbered {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut Rng) -> Option<HitRecord<'_>> {
+        let mut hit_record = self.object.hit(ray, t_min, t_max, rng)?;
+        hit_record.object_id = self.id;
+        Some(hit_record)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.object.bounding_box()
+    }
+
+    fn pdf_value(&self, origin: &Point, direction: &Vec3, rng: &mut Rng) -> f64 {
+        self.object.pdf_value(origin, direction, rng)
+    }
+
+    fn random(&self, origin: &Point, rng: &mut Rng) -> Vec3 {
+        self.object.random(origin, rng)
+    }
 }
 
 impl Hittable for HittableList {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut Rng) -> Option<HitRecord<'_>> {
         let mut temp_record = None;
         let mut closest_so_far = t_max;
 
         for object in self.objects.iter() {
-            match object.hit(ray, t_min, closest_so_far) {
+            match object.hit(ray, t_min, closest_so_far, rng) {
                 None => continue,
                 Some(hit_record) => {
                     closest_so_far = hit_record.t;
@@ -76,4 +171,63 @@ impl Hittable for HittableList {
 
         temp_record
     }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let mut objects = self.objects.iter();
+        let first = objects.next()?.bounding_box()?;
+        objects.try_fold(first, |acc, object| {
+            object
+                .bounding_box()
+                .map(|bbox| Aabb::surrounding(&acc, &bbox))
+        })
+    }
+
+    /// Every object is picked with the same probability
+    fn pdf_value(&self, origin: &Point, direction: &Vec3, rng: &mut Rng) -> f64 {
+        let sum: f64 = self
+            .objects
+            .iter()
+            .map(|object| object.pdf_value(origin, direction, rng))
+            .sum();
+        sum / self.objects.len() as f64
+    }
+
+    fn random(&self, origin: &Point, rng: &mut Rng) -> Vec3 {
+        let index = utils::random_range(rng, 0.0, self.objects.len() as f64) as usize;
+        self.objects[index].random(origin, rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Hittable, HittableList};
+    use crate::{
+        color::Color, material::Lambertian, point::Point, ray::Ray, sphere::Sphere, utils::Rng,
+        vec3::Vec3,
+    };
+    use rand::SeedableRng;
+    use std::sync::Arc;
+
+    #[test]
+    fn object_ids() {
+        let mut rng = Rng::seed_from_u64(0);
+        let material = Arc::new(Lambertian::new(Color::new([0.5, 0.5, 0.5])));
+        let mut list = HittableList::new();
+        for z in [-2.0, -4.0] {
+            list.add(Box::new(Sphere::new(
+                Point::new([0.0, 0.0, z]),
+                0.5,
+                material.clone(),
+            )));
+        }
+        let ray = |x: f64| Ray::new(Point::new([x, 0.0, 0.0]), Vec3::new([0.0, 0.0, -1.0]), 0.0);
+        let hit = list.hit(&ray(0.0), 0.001, f64::INFINITY, &mut rng).unwrap();
+        assert_eq!(hit.object_id, 0);
+
+        let list = list.numbered();
+        let hit = list.hit(&ray(0.0), 0.001, f64::INFINITY, &mut rng).unwrap();
+        assert_eq!(hit.object_id, 1);
+        let behind = list.hit(&ray(0.0), 3.0, f64::INFINITY, &mut rng).unwrap();
+        assert_eq!(behind.object_id, 2);
+    }
 }
@@ -0,0 +1,432 @@
+use crate::{
+    background::Background,
+    color::Color,
+    hittable::{HitRecord, Hittable},
+    material::ScatterRecord,
+    pdf::{HittablePdf, Pdf},
+    ray::Ray,
+    utils::{self, Rng},
+};
+use clap::ValueEnum;
+
+/// How the radiance along camera rays is estimated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Integrator {
+    /// `Ray::color`, diffuse bounces go towards lights half of the time
+    Mixture,
+    /// Path tracing with next-event estimation, combined by multiple
+    /// importance sampling
+    Path,
+}
+
+/// Sees what a camera ray hits first, `None` if it leaves the scene
+pub(crate) type OnFirstHit<'a> = &'a mut dyn FnMut(Option<&HitRecord>);
+
+/// How many surfaces a path may visit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Bounces {
+    /// Paths end at this many surfaces, even if they still carry light
+    pub(crate) max: u64,
+    /// Surfaces a path visits before Russian roulette may end it
+    pub(crate) roulette_after: u64,
+}
+
+impl Bounces {
+    /// Russian roulette for a path that has visited `depth` surfaces. Dim
+    /// paths are likely to end, the survivors are scaled by the returned
+    /// factor to make up for them, so the expected image stays the same.
+    pub(crate) fn roulette(&self, depth: u64, throughput: &Color, rng: &mut Rng) -> Option<f64> {
+        if depth < self.roulette_after {
+            return Some(1.0);
+        }
+        // Capped, so even bright paths can end inside closed rooms
+        let survival = throughput
+            .x()
+            .max(throughput.y())
+            .max(throughput.z())
+            .min(0.95);
+        if utils::random_float(rng) < survival {
+            Some(1.0 / survival)
+        } else {
+            None
+        }
+    }
+}
+
+/// Weighting of the light and BSDF strategies where both can find a light
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Heuristic {
+    Balance,
+    Power,
+}
+
+impl Heuristic {
+    /// Weight of a sample drawn with density `pdf` when the other strategy
+    /// would have drawn it with density `other`
+    pub(crate) fn weight(self, pdf: f64, other: f64) -> f64 {
+        let (pdf, other) = match self {
+            Self::Balance => (pdf, other),
+            Self::Power => (pdf * pdf, other * other),
+        };
+        if pdf + other > 0.0 {
+            pdf / (pdf + other)
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Radiance along `ray`, following at most `bounces.max` surfaces.
+///
+/// At every diffuse surface a shadow ray is sent towards a point drawn from
+/// `lights` and the bounce direction is drawn from the material. Lights
+/// found by either are weighted by `heuristic`, so both together count
+/// them once. Specular surfaces can only be followed, lights seen in them
+/// count fully. What `ray` hits is also passed to `on_first_hit`, if given.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn path_trace(
+    ray: Ray,
+    world: &dyn Hittable,
+    lights: Option<&dyn Hittable>,
+    background: &dyn Background,
+    bounces: Bounces,
+    heuristic: Heuristic,
+    mut on_first_hit: Option<OnFirstHit<'_>>,
+    rng: &mut Rng,
+) -> Color {
+    let mut radiance = Color::new([0.0, 0.0, 0.0]);
+    let mut throughput = Color::new([1.0, 1.0, 1.0]);
+    let mut ray = ray;
+    // Density the material drew `ray` with, `None` for camera rays and
+    // specular bounces, which light sampling cannot produce
+    let mut bsdf_pdf: Option<f64> = None;
+
+    for depth in 0..bounces.max {
+        let hit = world.hit(&ray, 0.001, f64::INFINITY, rng);
+        if let Some(on_first_hit) = on_first_hit.take() {
+            on_first_hit(hit.as_ref());
+        }
+        let rec = match hit {
+            Some(rec) => rec,
+            None => {
+                radiance = radiance + &throughput * &background.color(&ray);
+                break;
+            }
+        };
+
+        let emitted = rec.material.emitted(&ray, &rec);
+        if !emitted.near_zero() {
+            let weight = match (bsdf_pdf, lights) {
+                (Some(bsdf_pdf), Some(lights)) if is_sampled(lights, &ray, rec.t, rng) => {
+                    let light_pdf = lights.pdf_value(&ray.origin, &ray.direction, rng);
+                    heuristic.weight(bsdf_pdf, light_pdf)
+                }
+                _ => 1.0,
+            };
+            radiance = radiance + &(weight * &throughput) * &emitted;
+        }
+
+        let (scattered, weight, density) = match rec.material.scatter(&ray, &rec, rng) {
+            Some(ScatterRecord::Specular {
+                ray: scattered,
+                attenuation,
+            }) => (scattered, attenuation, None),
+            Some(ScatterRecord::Diffuse { pdf, attenuation }) => {
+                // Next-event estimation, unless the light would be one
+                // surface too many
+                if let Some(lights) = lights.filter(|_| depth + 1 < bounces.max) {
+                    let light_pdf = HittablePdf::new(lights, rec.point);
+                    let direction = light_pdf.generate(rng);
+                    let shadow = Ray::new(rec.point, direction, ray.time);
+                    let light_density = light_pdf.value(&direction, rng);
+                    let scattering_pdf = rec.material.scattering_pdf(&ray, &rec, &shadow);
+                    if light_density > 0.0 && scattering_pdf > 0.0 {
+                        let light = world
+                            .hit(&shadow, 0.001, f64::INFINITY, rng)
+                            .filter(|light| is_sampled(lights, &shadow, light.t, rng));
+                        if let Some(light) = light {
+                            let emitted = light.material.emitted(&shadow, &light);
+                            let weight =
+                                heuristic.weight(light_density, pdf.value(&direction, rng));
+                            let contribution = weight * scattering_pdf / light_density;
+                            radiance = radiance
+                                + &(contribution * &(&throughput * &attenuation)) * &emitted;
+                        }
+                    }
+                }
+
+                let direction = pdf.generate(rng);
+                let density = pdf.value(&direction, rng);
+                let scattered = Ray::new(rec.point, direction, ray.time);
+                let scattering_pdf = rec.material.scattering_pdf(&ray, &rec, &scattered);
+                if density <= 0.0 || scattering_pdf <= 0.0 {
+                    break;
+                }
+                (
+                    scattered,
+                    scattering_pdf / density * &attenuation,
+                    Some(density),
+                )
+            }
+            None => break,
+        };
+
+        throughput = &throughput * &weight;
+        match bounces.roulette(depth + 1, &throughput, rng) {
+            Some(scale) => throughput = scale * &throughput,
+            None => break,
+        }
+        ray = scattered;
+        bsdf_pdf = density;
+    }
+
+    radiance
+}
+
+/// Whether what `ray` hits at `t` is one of the sampled `lights`. Other
+/// emitters, like meshes and volumes, are only found by bouncing, so they
+/// count fully there and block shadow rays.
+fn is_sampled(lights: &dyn Hittable, ray: &Ray, t: f64, rng: &mut Rng) -> bool {
+    lights
+        .hit(ray, 0.001, f64::INFINITY, rng)
+        .is_some_and(|light| (light.t - t).abs() <= 1e-9 * t.max(1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_sampled, path_trace, Bounces, Heuristic};
+    use crate::{
+        aarect::AaRect,
+        background::SolidColor,
+        color::Color,
+        hittable::{Hittable, HittableList},
+        material::{Dielectric, DiffuseLight, Lambertian, Metal},
+        mesh::Mesh,
+        point::Point,
+        ray::Ray,
+        sphere::Sphere,
+        triangle::{Face, VertexBuffers},
+        utils::Rng,
+    };
+    use rand::SeedableRng;
+    use std::{f64::consts::PI, sync::Arc};
+
+    #[test]
+    fn weights_add_up() {
+        for heuristic in [Heuristic::Balance, Heuristic::Power] {
+            let sum = heuristic.weight(0.3, 2.0) + heuristic.weight(2.0, 0.3);
+            assert!((sum - 1.0).abs() < 1e-12);
+            assert_eq!(heuristic.weight(1.0, 0.0), 1.0);
+        }
+        assert!(Heuristic::Power.weight(2.0, 1.0) > Heuristic::Balance.weight(2.0, 1.0));
+    }
+
+    /// A grey floor under a square lamp, a mirror ball and a glass ball
+    fn lamp_scene() -> (HittableList, Arc<AaRect>) {
+        let light = Arc::new(
+            AaRect::xz(
+                (-0.5, 0.5),
+                (-0.5, 0.5),
+                1.0,
+                Arc::new(DiffuseLight::new(Color::new([4.0, 4.0, 4.0]))),
+            )
+            .flipped(),
+        );
+        let mut world = HittableList::new();
+        world.add(Box::new(AaRect::xz(
+            (-10.0, 10.0),
+            (-10.0, 10.0),
+            0.0,
+            Arc::new(Lambertian::new(Color::new([0.5, 0.5, 0.5]))),
+        )));
+        world.add(Box::new(Sphere::new(
+            Point::new([1.0, 0.3, 0.0]),
+            0.3,
+            Arc::new(Metal::new(Color::new([0.9, 0.9, 0.9]), 0.0)),
+        )));
+        world.add(Box::new(Sphere::new(
+            Point::new([-1.0, 0.3, 0.0]),
+            0.3,
+            Arc::new(Dielectric::new(1.5)),
+        )));
+        world.add(Box::new(Arc::clone(&light)));
+        (world, light)
+    }
+
+    #[test]
+    fn roulette() {
+        let mut rng = Rng::seed_from_u64(0);
+        let bounces = Bounces {
+            max: 10,
+            roulette_after: 3,
+        };
+        let dim = Color::new([0.1, 0.2, 0.1]);
+        assert_eq!(bounces.roulette(2, &dim, &mut rng), Some(1.0));
+        assert_eq!(
+            bounces.roulette(3, &Color::new([0.0, 0.0, 0.0]), &mut rng),
+            None
+        );
+
+        // Survivors make up for the paths that ended
+        let runs = 100_000;
+        let total: f64 = (0..runs)
+            .filter_map(|_| bounces.roulette(3, &dim, &mut rng))
+            .sum();
+        assert!((total / runs as f64 - 1.0).abs() < 0.02);
+    }
+
+    #[test]
+    fn converges_like_mixture() {
+        let (world, light) = lamp_scene();
+        let background = SolidColor::new(Color::new([0.0, 0.0, 0.0]));
+        let runs = 20_000;
+        let full = Bounces {
+            max: 5,
+            roulette_after: 5,
+        };
+        let roulette = Bounces {
+            max: 5,
+            roulette_after: 1,
+        };
+
+        // Onto the floor next to the balls, and onto each ball from above
+        for target in [
+            Point::new([0.0, 0.0, 0.3]),
+            Point::new([1.0, 0.3, 0.0]),
+            Point::new([-1.0, 0.3, 0.0]),
+        ] {
+            let origin = Point::new([0.0, 0.9, 0.2]);
+            let ray = Ray::new(origin, target - origin, 0.0);
+            let mean = |estimate: &mut dyn FnMut(&mut Rng) -> Color| {
+                let mut rng = Rng::seed_from_u64(0);
+                (0..runs).map(|_| estimate(&mut rng).x()).sum::<f64>() / runs as f64
+            };
+            let lights: Option<&dyn Hittable> = Some(light.as_ref());
+            let mixture = mean(&mut |rng| ray.color(&world, lights, &background, full, None, rng));
+
+            let mut estimates = vec![mean(&mut |rng| {
+                ray.color(&world, lights, &background, roulette, None, rng)
+            })];
+            for heuristic in [Heuristic::Balance, Heuristic::Power] {
+                for bounces in [full, roulette] {
+                    estimates.push(mean(&mut |rng| {
+                        path_trace(
+                            ray,
+                            &world,
+                            lights,
+                            &background,
+                            bounces,
+                            heuristic,
+                            None,
+                            rng,
+                        )
+                    }));
+                }
+            }
+            for estimate in estimates {
+                assert!(
+                    (estimate - mixture).abs() < 0.05 * mixture,
+                    "{} {}",
+                    estimate,
+                    mixture
+                );
+            }
+
+            // Without lights to sample every light is found by bouncing
+            let unsampled = mean(&mut |rng| {
+                path_trace(
+                    ray,
+                    &world,
+                    None,
+                    &background,
+                    full,
+                    Heuristic::Power,
+                    None,
+                    rng,
+                )
+            });
+            assert!((unsampled - mixture).abs() < 0.1 * mixture);
+        }
+    }
+
+    #[test]
+    fn emitters_that_are_not_lights_occlude() {
+        let (mut world, light) = lamp_scene();
+        // A glowing 2x2 mesh halfway between the floor and the lamp, which
+        // is not sampled as a light and hides the lamp from the origin
+        let vertices = VertexBuffers {
+            positions: [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+                .iter()
+                .map(|&(x, z)| Point::new([x, 0.5, z]))
+                .collect(),
+            ..VertexBuffers::default()
+        };
+        world.add(Box::new(Mesh::new(
+            Arc::new(vertices),
+            &[Face::new([0, 1, 2]), Face::new([0, 2, 3])],
+            Arc::new(DiffuseLight::new(Color::new([1.0, 1.0, 1.0]))),
+        )));
+        let background = SolidColor::new(Color::new([0.0, 0.0, 0.0]));
+        let bounces = Bounces {
+            max: 5,
+            roulette_after: 5,
+        };
+
+        // The floor reflects half of the mesh's light, which covers this
+        // share of the origin's cosine-weighted hemisphere
+        let side: f64 = 1.0 / 0.5;
+        let diagonal = (1.0 + side * side).sqrt();
+        let form_factor = 4.0 * side / diagonal * (side / diagonal).atan() / PI;
+        let expected = 0.5 * form_factor;
+
+        // Shadow rays towards the lamp find the mesh instead
+        let mut rng = Rng::seed_from_u64(0);
+        let up = Ray::new(
+            Point::new([0.0, 0.0, 0.0]),
+            Point::new([0.0, 1.0, 0.0]),
+            0.0,
+        );
+        let aside = Ray::new(
+            Point::new([3.0, 0.0, 0.0]),
+            Point::new([-3.0, 1.0, 0.0]),
+            0.0,
+        );
+        for (shadow, sampled) in [(up, false), (aside, true)] {
+            let hit = world.hit(&shadow, 0.001, f64::INFINITY, &mut rng).unwrap();
+            assert_eq!(
+                is_sampled(light.as_ref(), &shadow, hit.t, &mut rng),
+                sampled
+            );
+        }
+
+        let origin = Point::new([0.0, 0.25, 0.1]);
+        let ray = Ray::new(origin, Point::new([0.0, 0.0, 0.0]) - origin, 0.0);
+        let lights: Option<&dyn Hittable> = Some(light.as_ref());
+        let runs = 20_000;
+        for heuristic in [Heuristic::Balance, Heuristic::Power] {
+            let mut rng = Rng::seed_from_u64(0);
+            let mean = (0..runs)
+                .map(|_| {
+                    path_trace(
+                        ray,
+                        &world,
+                        lights,
+                        &background,
+                        bounces,
+                        heuristic,
+                        None,
+                        &mut rng,
+                    )
+                    .x()
+                })
+                .sum::<f64>()
+                / runs as f64;
+            assert!(
+                (mean - expected).abs() < 0.03 * expected,
+                "{} {}",
+                mean,
+                expected
+            );
+        }
+    }
+}
@@ -1,71 +1,188 @@
+mod aabb;
+mod aarect;
+mod aov;
+mod background;
+mod bvh;
 mod camera;
+mod checkpoint;
+mod cli;
 mod color;
+mod constant_medium;
+mod denoise;
 mod hittable;
+mod integrator;
 mod material;
+mod mesh;
+mod obj;
+mod onb;
+mod output;
+mod pdf;
+mod perlin;
 mod point;
 mod ray;
+mod render;
+mod scene;
 mod sphere;
+mod texture;
+mod tonemap;
+mod transform;
+mod triangle;
 mod utils;
 mod vec3;
 
-use crate::{camera::Camera, hittable::HittableList, point::Point};
+use crate::{
+    aov::FirstHit,
+    background::{Background, Gradient},
+    bvh::BvhNode,
+    camera::Camera,
+    checkpoint::Checkpoint,
+    cli::Args,
+    color::Color,
+    hittable::{Hittable, HittableList},
+    output::OutputError,
+    render::{Framebuffer, Renderer},
+};
 use indicatif::{ProgressBar, ProgressStyle};
+use rand::SeedableRng;
+use std::{
+    process,
+    time::{Duration, Instant},
+};
 
 fn main() {
+    let (args, checkpoint) = Args::parse_and_validate();
+
+    if let Some(threads) = args.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .expect("the global thread pool is only configured once");
+    }
+
     // Image
-    let aspect_ratio = 3.0 / 2.0;
-    let image_width = 1200 as u64;
-    let image_height = ((image_width as f64) / aspect_ratio) as u64;
-    let samples_per_pixel = 10;
-    let max_depth = 50;
+    let image_width = args.width;
+    let image_height = args.image_height();
+
+    let seed = args.seed.expect("parsing picks a seed");
+    eprintln!("Rendering with seed {}", seed);
 
     // World
-    let mut world = HittableList::new();
-    utils::random_scene(&mut world);
+    let (world, lights, camera_settings, scene_background) = match &args.scene {
+        Some(path) => match scene::load(path) {
+            Ok(scene) => (scene.world, scene.lights, scene.camera, scene.background),
+            Err(err) => {
+                eprintln!("{}: {}", path.display(), err);
+                process::exit(1);
+            }
+        },
+        None => {
+            let mut scene_rng = utils::Rng::seed_from_u64(seed);
+            let mut world = HittableList::new();
+            utils::random_scene(&mut world, &mut scene_rng);
+            (world, HittableList::new(), args.camera_settings(), None)
+        }
+    };
+    let world = BvhNode::new(world.numbered());
+    let lights = (!lights.is_empty()).then_some(&lights as &dyn Hittable);
 
-    // Camera
-    let look_from = point!(13.0, 2.0, 3.0);
-    let look_at = point!(0.0, 0.0, 0.0);
-    let view_up = vec3!(0.0, 1.0, 0.0);
-    let vertical_fov = 20.0;
-    let dist_to_focus = 10.0;
-    let aperture = 0.1;
-    let camera = Camera::new(
-        look_from,
-        look_at,
-        view_up,
-        vertical_fov,
-        aspect_ratio,
-        aperture,
-        dist_to_focus,
-    );
+    let background: Box<dyn Background> = match args.background() {
+        Ok(Some(background)) => background,
+        Ok(None) => scene_background.unwrap_or_else(|| Box::new(Gradient::default())),
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
 
-    println!("P3");
-    println!("{} {}", image_width, image_height);
-    println!("255");
+    // Camera
+    let camera = Camera::from_settings(&camera_settings, args.aspect_ratio);
 
-    let bar = ProgressBar::new(image_height * image_width);
+    let bar = ProgressBar::new((image_width * image_height) as u64 * u64::from(args.samples));
     bar.set_style(
         ProgressStyle::default_bar()
-            .template("[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}")
+            .template("[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} samples {msg}")
             .progress_chars("=> "),
     );
-    for j in (0..image_height).rev() {
-        //eprintln!("Scanlines remaining: {}", j);
-        for i in 0..image_width {
-            let mut pixel_color = color!(0.0, 0.0, 0.0);
-            for _s in 0..samples_per_pixel {
-                let u = (i as f64 + utils::random_float()) / ((image_width - 1) as f64);
-                let v = (j as f64 + utils::random_float()) / ((image_height - 1) as f64);
-                let ray = camera.get_ray(u, v);
-                pixel_color = pixel_color + ray.color(&world, max_depth);
+
+    // Resumed renders keep recording first hits, or their buffers would be
+    // short of samples next time
+    let (mut framebuffer, first_hits) = match checkpoint {
+        Some(checkpoint) => {
+            let done = checkpoint.framebuffer.samples().max().unwrap_or(0);
+            eprintln!("Resuming from {} samples per pixel", done);
+            (checkpoint.framebuffer, checkpoint.first_hits)
+        }
+        None => (
+            Framebuffer::new(image_width, image_height),
+            args.first_hits(),
+        ),
+    };
+
+    let renderer = Renderer {
+        world: &world,
+        lights,
+        background: background.as_ref(),
+        camera: &camera,
+        samples_per_pixel: args.samples,
+        adaptive: args.adaptive(),
+        bounces: args.bounces(),
+        integrator: args.integrator,
+        heuristic: args.mis_heuristic,
+        first_hits,
+        seed,
+    };
+    let interval = Duration::from_secs_f64(args.checkpoint_interval);
+    let mut last_checkpoint = Instant::now();
+    renderer.render(&mut framebuffer, &bar, |framebuffer| {
+        if let Some(path) = args.checkpoint_path() {
+            if last_checkpoint.elapsed() >= interval {
+                // A failed checkpoint is no reason to give up the render
+                let saved = Checkpoint::save(path, &args.command_line, first_hits, framebuffer)
+                    .map_err(|err| format!("{}: {}", path.display(), err))
+                    .and_then(|()| {
+                        write_outputs(&args, framebuffer).map_err(|err| err.to_string())
+                    });
+                if let Err(err) = saved {
+                    bar.println(err);
+                }
+                last_checkpoint = Instant::now();
             }
+        }
+    });
+    bar.finish();
 
-            println!("{}", pixel_color.write(samples_per_pixel));
-            bar.inc(1);
+    if let Some(path) = args.checkpoint_path() {
+        if let Err(err) = Checkpoint::save(path, &args.command_line, first_hits, &framebuffer) {
+            eprintln!("{}: {}", path.display(), err);
+            process::exit(1);
         }
     }
+    if let Err(err) = write_outputs(&args, &framebuffer) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
 
-    bar.finish();
-    //eprintln!("Done.");
+/// Writes the image, denoised if asked to, and every other requested output
+fn write_outputs(args: &Args, framebuffer: &Framebuffer) -> Result<(), OutputError> {
+    let width = framebuffer.width;
+    let first_hits: Vec<FirstHit> = framebuffer.first_hits().collect();
+    let mut colors: Vec<Color> = framebuffer.colors().collect();
+    if let Some(denoiser) = args.denoiser() {
+        let variances: Vec<f64> = framebuffer.variances().collect();
+        colors = denoiser.apply(width, &colors, &variances, &first_hits);
+    }
+
+    let settings = args.output_settings();
+    output::write_image(&args.output, width, &colors, &settings)?;
+    for (aov, path) in &args.aovs {
+        output::write_aov(path, *aov, width, &first_hits, &settings)?;
+    }
+    if let Some(path) = &args.aov_exr {
+        output::write_aov_layers(path, width, &colors, &first_hits, &settings)?;
+    }
+    if let Some(path) = &args.sample_heatmap {
+        output::write_sample_heatmap(path, framebuffer, args.samples, &settings)?;
+    }
+    Ok(())
 }
@@ -1,58 +1,171 @@
-use crate::{color::Color, hittable::HitRecord, ray::Ray, utils, vec3::Vec3};
+use crate::{
+    color::Color,
+    hittable::HitRecord,
+    pdf::{CosinePdf, Pdf, SpherePdf},
+    ray::Ray,
+    texture::{SolidColor, Texture},
+    utils::{self, Rng},
+    vec3::Vec3,
+};
+use std::{f64::consts::PI, sync::Arc};
 
-pub(crate) trait Material {
-    fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord) -> Option<(Ray, Color)>;
+/// How a material scatters an incoming ray
+pub(crate) enum ScatterRecord {
+    /// Exactly one outgoing ray, like a mirror reflection, followed as is
+    Specular { ray: Ray, attenuation: Color },
+    /// The outgoing direction may be drawn from `pdf` or from any other
+    /// density, weighted by `Material::scattering_pdf`
+    Diffuse {
+        pdf: Box<dyn Pdf>,
+        attenuation: Color,
+    },
 }
 
-#[derive(Debug, Clone, Copy)]
+/// What a material is, regardless of its parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MaterialKind {
+    Lambertian = 1,
+    Metal,
+    Dielectric,
+    DiffuseLight,
+    Isotropic,
+}
+
+impl MaterialKind {
+    /// The kind with the given discriminant
+    pub(crate) fn from_id(id: u32) -> Option<Self> {
+        [
+            Self::Lambertian,
+            Self::Metal,
+            Self::Dielectric,
+            Self::DiffuseLight,
+            Self::Isotropic,
+        ]
+        .iter()
+        .copied()
+        .find(|&kind| kind as u32 == id)
+    }
+}
+
+pub(crate) trait Material: Send + Sync {
+    fn kind(&self) -> MaterialKind;
+
+    fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord, rng: &mut Rng)
+        -> Option<ScatterRecord>;
+
+    /// Density of diffuse scattering from `ray_in` into `scattered`, with
+    /// respect to solid angle
+    fn scattering_pdf(&self, _ray_in: &Ray, _hit_record: &HitRecord, _scattered: &Ray) -> f64 {
+        0.0
+    }
+
+    /// Radiance emitted from the hit point, black for everything but lights
+    fn emitted(&self, _ray_in: &Ray, _hit_record: &HitRecord) -> Color {
+        Color::new([0.0, 0.0, 0.0])
+    }
+
+    /// Surface color at the hit point, which the denoiser separates from the
+    /// lighting. Materials without one, like glass and lights, are white.
+    fn albedo(&self, _hit_record: &HitRecord) -> Color {
+        Color::new([1.0, 1.0, 1.0])
+    }
+}
+
+#[derive(Clone)]
 pub(crate) struct Lambertian {
-    albedo: Color,
+    albedo: Arc<dyn Texture>,
 }
 
 impl Lambertian {
     pub(crate) fn new(albedo: Color) -> Self {
+        Self::with_texture(Arc::new(SolidColor::new(albedo)))
+    }
+
+    pub(crate) fn with_texture(albedo: Arc<dyn Texture>) -> Self {
         Self { albedo }
     }
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, _ray_in: &Ray, hit_record: &HitRecord) -> Option<(Ray, Color)> {
-        let mut scatter_direction = hit_record.normal + Vec3::random_in_unit_sphere();
+    fn kind(&self) -> MaterialKind {
+        MaterialKind::Lambertian
+    }
 
-        if scatter_direction.near_zero() {
-            scatter_direction = hit_record.normal.clone();
-        }
+    fn scatter(
+        &self,
+        _ray_in: &Ray,
+        hit_record: &HitRecord,
+        _rng: &mut Rng,
+    ) -> Option<ScatterRecord> {
+        let attenuation = self
+            .albedo
+            .value(hit_record.u, hit_record.v, &hit_record.point);
+        Some(ScatterRecord::Diffuse {
+            pdf: Box::new(CosinePdf::new(&hit_record.normal)),
+            attenuation,
+        })
+    }
+
+    fn scattering_pdf(&self, _ray_in: &Ray, hit_record: &HitRecord, scattered: &Ray) -> f64 {
+        let cosine = hit_record.normal.dot(&scattered.direction.as_unit_vec());
+        (cosine / PI).max(0.0)
+    }
 
-        let ray = Ray::new(hit_record.point, scatter_direction);
-        Some((ray, self.albedo))
+    fn albedo(&self, hit_record: &HitRecord) -> Color {
+        self.albedo
+            .value(hit_record.u, hit_record.v, &hit_record.point)
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Clone)]
 pub(crate) struct Metal {
-    albedo: Color,
+    albedo: Arc<dyn Texture>,
     fuzz: f64,
 }
 
 impl Metal {
     pub(crate) fn new(albedo: Color, fuzz: f64) -> Self {
+        Self::with_texture(Arc::new(SolidColor::new(albedo)), fuzz)
+    }
+
+    pub(crate) fn with_texture(albedo: Arc<dyn Texture>, fuzz: f64) -> Self {
         Self { albedo, fuzz }
     }
 }
 
 impl Material for Metal {
-    fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord) -> Option<(Ray, Vec3)> {
+    fn kind(&self) -> MaterialKind {
+        MaterialKind::Metal
+    }
+
+    fn scatter(
+        &self,
+        ray_in: &Ray,
+        hit_record: &HitRecord,
+        rng: &mut Rng,
+    ) -> Option<ScatterRecord> {
         let reflected_direction =
             Vec3::reflect(&ray_in.direction.as_unit_vec(), &hit_record.normal);
-        let fuzzy_direction = reflected_direction + self.fuzz * &Vec3::random_in_unit_sphere();
-        let scattered = Ray::new(hit_record.point, fuzzy_direction);
+        let fuzzy_direction = reflected_direction + self.fuzz * &Vec3::random_in_unit_sphere(rng);
+        let scattered = Ray::new(hit_record.point, fuzzy_direction, ray_in.time);
 
         if scattered.direction.dot(&hit_record.normal) > 0.0 {
-            Some((scattered, self.albedo))
+            let attenuation = self
+                .albedo
+                .value(hit_record.u, hit_record.v, &hit_record.point);
+            Some(ScatterRecord::Specular {
+                ray: scattered,
+                attenuation,
+            })
         } else {
             None
         }
     }
+
+    fn albedo(&self, hit_record: &HitRecord) -> Color {
+        self.albedo
+            .value(hit_record.u, hit_record.v, &hit_record.point)
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -77,7 +190,16 @@ impl Dielectric {
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord) -> Option<(Ray, Vec3)> {
+    fn kind(&self) -> MaterialKind {
+        MaterialKind::Dielectric
+    }
+
+    fn scatter(
+        &self,
+        ray_in: &Ray,
+        hit_record: &HitRecord,
+        rng: &mut Rng,
+    ) -> Option<ScatterRecord> {
         let refraction_ratio = if hit_record.front_face {
             1.0 / self.index_of_refraction
         } else {
@@ -89,14 +211,96 @@ impl Material for Dielectric {
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
         let cannot_refract = refraction_ratio * sin_theta > 1.0;
         let direction = if cannot_refract
-            || Self::reflectance(cos_theta, refraction_ratio) > utils::random_float()
+            || Self::reflectance(cos_theta, refraction_ratio) > utils::random_float(rng)
         {
             Vec3::reflect(&unit_direction, &hit_record.normal)
         } else {
             Vec3::refract(&unit_direction, &hit_record.normal, refraction_ratio)
         };
 
-        let scattered = Ray::new(hit_record.point, direction);
-        Some((scattered, Color::new([1.0, 1.0, 1.0])))
+        let scattered = Ray::new(hit_record.point, direction, ray_in.time);
+        Some(ScatterRecord::Specular {
+            ray: scattered,
+            attenuation: Color::new([1.0, 1.0, 1.0]),
+        })
+    }
+}
+
+/// Emits light in every direction and does not scatter
+#[derive(Clone)]
+pub(crate) struct DiffuseLight {
+    emit: Arc<dyn Texture>,
+}
+
+impl DiffuseLight {
+    pub(crate) fn new(emit: Color) -> Self {
+        Self::with_texture(Arc::new(SolidColor::new(emit)))
+    }
+
+    pub(crate) fn with_texture(emit: Arc<dyn Texture>) -> Self {
+        Self { emit }
+    }
+}
+
+impl Material for DiffuseLight {
+    fn kind(&self) -> MaterialKind {
+        MaterialKind::DiffuseLight
+    }
+
+    fn scatter(
+        &self,
+        _ray_in: &Ray,
+        _hit_record: &HitRecord,
+        _rng: &mut Rng,
+    ) -> Option<ScatterRecord> {
+        None
+    }
+
+    fn emitted(&self, _ray_in: &Ray, hit_record: &HitRecord) -> Color {
+        self.emit
+            .value(hit_record.u, hit_record.v, &hit_record.point)
+    }
+}
+
+/// Scatters in a uniformly random direction, the phase function of
+/// `ConstantMedium` volumes
+#[derive(Clone)]
+pub(crate) struct Isotropic {
+    albedo: Arc<dyn Texture>,
+}
+
+impl Isotropic {
+    pub(crate) fn with_texture(albedo: Arc<dyn Texture>) -> Self {
+        Self { albedo }
+    }
+}
+
+impl Material for Isotropic {
+    fn kind(&self) -> MaterialKind {
+        MaterialKind::Isotropic
+    }
+
+    fn scatter(
+        &self,
+        _ray_in: &Ray,
+        hit_record: &HitRecord,
+        _rng: &mut Rng,
+    ) -> Option<ScatterRecord> {
+        let attenuation = self
+            .albedo
+            .value(hit_record.u, hit_record.v, &hit_record.point);
+        Some(ScatterRecord::Diffuse {
+            pdf: Box::new(SpherePdf),
+            attenuation,
+        })
+    }
+
+    fn scattering_pdf(&self, _ray_in: &Ray, _hit_record: &HitRecord, _scattered: &Ray) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+
+    fn albedo(&self, hit_record: &HitRecord) -> Color {
+        self.albedo
+            .value(hit_record.u, hit_record.v, &hit_record.point)
     }
 }
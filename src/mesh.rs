@@ -0,0 +1,97 @@
+use crate::{
+    aabb::Aabb,
+    bvh::BvhNode,
+    hittable::{HitRecord, Hittable, HittableList},
+    material::Material,
+    ray::Ray,
+    triangle::{Face, Triangle, VertexBuffers},
+    utils::Rng,
+};
+use std::sync::Arc;
+
+/// Triangles sharing one set of vertex buffers and one material, with their
+/// own BVH so the whole mesh is a single object in the scene
+pub(crate) struct Mesh {
+    triangles: BvhNode,
+}
+
+impl Mesh {
+    pub(crate) fn new(
+        vertices: Arc<VertexBuffers>,
+        faces: &[Face],
+        material: Arc<dyn Material>,
+    ) -> Self {
+        assert!(!faces.is_empty(), "a mesh needs at least one face");
+        let mut triangles = HittableList::new();
+        for face in faces {
+            triangles.add(Box::new(Triangle::new(
+                Arc::clone(&vertices),
+                *face,
+                Arc::clone(&material),
+            )));
+        }
+        Self {
+            triangles: BvhNode::new(triangles),
+        }
+    }
+}
+
+impl Hittable for Mesh {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut Rng) -> Option<HitRecord<'_>> {
+        self.triangles.hit(ray, t_min, t_max, rng)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.triangles.bounding_box()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Mesh;
+    use crate::{
+        color::Color,
+        hittable::Hittable,
+        material::Lambertian,
+        point::Point,
+        ray::Ray,
+        triangle::{Face, VertexBuffers},
+        utils::Rng,
+        vec3::Vec3,
+    };
+    use rand::SeedableRng;
+    use std::sync::Arc;
+
+    #[test]
+    fn quad() {
+        let mut rng = Rng::seed_from_u64(0);
+        let vertices = VertexBuffers {
+            positions: vec![
+                Point::new([0.0, 0.0, 0.0]),
+                Point::new([1.0, 0.0, 0.0]),
+                Point::new([1.0, 1.0, 0.0]),
+                Point::new([0.0, 1.0, 0.0]),
+            ],
+            ..VertexBuffers::default()
+        };
+        let faces = [Face::new([0, 1, 2]), Face::new([0, 2, 3])];
+        let material = Arc::new(Lambertian::new(Color::new([0.5, 0.5, 0.5])));
+        let mesh = Mesh::new(Arc::new(vertices), &faces, material);
+
+        let bbox = mesh.bounding_box().unwrap();
+        assert!(bbox.minimum.x() < 0.0 && bbox.maximum.y() > 1.0);
+
+        for (x, y) in [(0.75, 0.25), (0.25, 0.75)] {
+            let ray = Ray::new(Point::new([x, y, 2.0]), Vec3::new([0.0, 0.0, -1.0]), 0.0);
+            let hit = mesh.hit(&ray, 0.001, f64::INFINITY, &mut rng).unwrap();
+            assert_eq!(hit.t, 2.0);
+            assert!(hit.front_face);
+        }
+        let outside = Ray::new(
+            Point::new([1.5, 0.5, 2.0]),
+            Vec3::new([0.0, 0.0, -1.0]),
+            0.0,
+        );
+        assert!(mesh.hit(&outside, 0.001, f64::INFINITY, &mut rng).is_none());
+    }
+}
@@ -0,0 +1,545 @@
+//! Wavefront OBJ models and their MTL material libraries.
+//!
+//! Reads `v`, `vt`, `vn` and `f` statements, where polygons are split into
+//! triangle fans and negative indices count back from the latest vertex, as
+//! well as `mtllib` and `usemtl`. Groups, smoothing groups, lines and other
+//! statements are ignored.
+//!
+//! MTL materials become the closest material this crate has:
+//! - a non-black `Ke` makes a `DiffuseLight`
+//! - `d` below 1 (or `Tr` above 0) makes a `Dielectric` with `Ni` as its index
+//!   of refraction
+//! - a `Ks` brighter than `Kd` makes a `Metal`, the higher `Ns` the less fuzz
+//! - anything else is `Lambertian`, colored by `map_Kd` or `Kd`
+
+use crate::{
+    color::Color,
+    hittable::HittableList,
+    material::{Dielectric, DiffuseLight, Lambertian, Material, Metal},
+    mesh::Mesh,
+    point::Point,
+    texture::{ImageTexture, WrapMode},
+    triangle::{Face, VertexBuffers},
+    vec3::Vec3,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+#[derive(Debug)]
+pub(crate) enum ObjError {
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Invalid {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            Self::Invalid {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+        }
+    }
+}
+
+/// Position in a file, for error messages
+#[derive(Clone, Copy)]
+struct Location<'a> {
+    path: &'a Path,
+    line: usize,
+}
+
+impl Location<'_> {
+    fn error(&self, message: String) -> ObjError {
+        ObjError::Invalid {
+            path: self.path.to_path_buf(),
+            line: self.line,
+            message,
+        }
+    }
+
+    fn floats(&self, args: &[&str], min: usize, max: usize) -> Result<Vec<f64>, ObjError> {
+        if args.len() < min || args.len() > max {
+            let expected = if min == max {
+                min.to_string()
+            } else {
+                format!("{} to {}", min, max)
+            };
+            return Err(self.error(format!("expected {} numbers, got {}", expected, args.len())));
+        }
+        args.iter()
+            .map(|arg| match arg.parse::<f64>() {
+                Ok(value) if value.is_finite() => Ok(value),
+                _ => Err(self.error(format!("invalid number '{}'", arg))),
+            })
+            .collect()
+    }
+
+    fn float(&self, args: &[&str]) -> Result<f64, ObjError> {
+        Ok(self.floats(args, 1, 1)?[0])
+    }
+
+    /// A color given as `r g b`, or a single gray value
+    fn color(&self, args: &[&str]) -> Result<Color, ObjError> {
+        let values = self.floats(args, 1, 3)?;
+        if values.iter().any(|value| *value < 0.0) {
+            return Err(self.error("color components must not be negative".to_string()));
+        }
+        match values[..] {
+            [gray] => Ok(Color::new([gray, gray, gray])),
+            [r, g, b] => Ok(Color::new([r, g, b])),
+            _ => Err(self.error("expected 1 or 3 color components".to_string())),
+        }
+    }
+
+    /// Resolves a 1-based (or negative, relative) index into a buffer of `len`
+    fn index(&self, token: &str, len: usize, kind: &str) -> Result<usize, ObjError> {
+        let index: i64 = token
+            .parse()
+            .map_err(|_| self.error(format!("invalid {} index '{}'", kind, token)))?;
+        let resolved = if index < 0 {
+            len as i64 + index
+        } else {
+            index - 1
+        };
+        if index == 0 || resolved < 0 || resolved >= len as i64 {
+            return Err(self.error(format!(
+                "{} index {} out of range, there are {}",
+                kind, index, len
+            )));
+        }
+        Ok(resolved as usize)
+    }
+
+    /// The rest of a statement, for names and paths that may contain spaces
+    fn rest<'s>(&self, line: &'s str, keyword: &str) -> Result<&'s str, ObjError> {
+        let rest = line.trim()[keyword.len()..].trim();
+        if rest.is_empty() {
+            Err(self.error(format!("'{}' needs an argument", keyword)))
+        } else {
+            Ok(rest)
+        }
+    }
+}
+
+fn read(path: &Path) -> Result<String, ObjError> {
+    fs::read_to_string(path).map_err(|source| ObjError::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// Loads a model as one mesh per material, all sharing the vertex buffers.
+/// Faces before any `usemtl` get `default_material`.
+pub(crate) fn load(
+    path: &Path,
+    default_material: Arc<dyn Material>,
+) -> Result<HittableList, ObjError> {
+    parse(&read(path)?, path, default_material)
+}
+
+/// Parses an OBJ file read from `path`, material libraries are loaded
+/// relative to it
+pub(crate) fn parse(
+    source: &str,
+    path: &Path,
+    default_material: Arc<dyn Material>,
+) -> Result<HittableList, ObjError> {
+    let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let mut vertices = VertexBuffers::default();
+    let mut materials: HashMap<String, Arc<dyn Material>> = HashMap::new();
+    let mut groups: BTreeMap<Option<String>, Vec<Face>> = BTreeMap::new();
+    let mut current_material = None;
+
+    for (number, line) in source.lines().enumerate() {
+        let location = Location {
+            path,
+            line: number + 1,
+        };
+        let line = line.split('#').next().unwrap_or_default();
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        match keyword {
+            "v" => {
+                // An optional fourth weight is ignored
+                let values = location.floats(&args, 3, 4)?;
+                vertices
+                    .positions
+                    .push(Point::new([values[0], values[1], values[2]]));
+            }
+            "vt" => {
+                let values = location.floats(&args, 1, 3)?;
+                vertices
+                    .uvs
+                    .push((values[0], values.get(1).copied().unwrap_or(0.0)));
+            }
+            "vn" => {
+                let values = location.floats(&args, 3, 3)?;
+                let normal = Vec3::new([values[0], values[1], values[2]]);
+                if normal.near_zero() {
+                    return Err(location.error("normals must not be zero".to_string()));
+                }
+                vertices.normals.push(normal);
+            }
+            "f" => {
+                let faces = groups.entry(current_material.clone()).or_default();
+                faces.extend(polygon(&location, &args, &vertices)?);
+            }
+            "mtllib" => {
+                for library in location.rest(line, keyword)?.split_whitespace() {
+                    let library = base_dir.join(library);
+                    materials.extend(parse_mtl(&read(&library)?, &library)?);
+                }
+            }
+            "usemtl" => {
+                let name = location.rest(line, keyword)?;
+                if !materials.contains_key(name) {
+                    return Err(location.error(format!("unknown material '{}'", name)));
+                }
+                current_material = Some(name.to_string());
+            }
+            _ => {}
+        }
+    }
+
+    if groups.is_empty() {
+        return Err(Location { path, line: 1 }.error("the model has no faces".to_string()));
+    }
+
+    let vertices = Arc::new(vertices);
+    let mut model = HittableList::new();
+    for (name, faces) in groups {
+        let material = match name {
+            Some(name) => Arc::clone(&materials[&name]),
+            None => Arc::clone(&default_material),
+        };
+        model.add(Box::new(Mesh::new(Arc::clone(&vertices), &faces, material)));
+    }
+    Ok(model)
+}
+
+/// Splits a polygon into a fan of triangles around its first corner.
+/// Normals and UVs are only kept if every corner has them.
+fn polygon(
+    location: &Location<'_>,
+    args: &[&str],
+    vertices: &VertexBuffers,
+) -> Result<Vec<Face>, ObjError> {
+    if args.len() < 3 {
+        return Err(location.error(format!(
+            "a face needs at least 3 vertices, got {}",
+            args.len()
+        )));
+    }
+
+    let mut positions = Vec::with_capacity(args.len());
+    let mut uvs = Vec::with_capacity(args.len());
+    let mut normals = Vec::with_capacity(args.len());
+    for arg in args {
+        let parts: Vec<&str> = arg.split('/').collect();
+        if parts.len() > 3 {
+            return Err(location.error(format!("invalid face vertex '{}'", arg)));
+        }
+        positions.push(location.index(parts[0], vertices.positions.len(), "vertex")?);
+        uvs.push(match parts.get(1) {
+            Some(part) if !part.is_empty() => {
+                Some(location.index(part, vertices.uvs.len(), "texture coordinate")?)
+            }
+            _ => None,
+        });
+        normals.push(match parts.get(2) {
+            Some(part) if !part.is_empty() => {
+                Some(location.index(part, vertices.normals.len(), "normal")?)
+            }
+            _ => None,
+        });
+    }
+    let uvs: Option<Vec<usize>> = uvs.into_iter().collect();
+    let normals: Option<Vec<usize>> = normals.into_iter().collect();
+
+    let corners = |indices: &[usize], i: usize| [indices[0], indices[i], indices[i + 1]];
+    Ok((1..positions.len() - 1)
+        .map(|i| Face {
+            positions: corners(&positions, i),
+            normals: normals.as_ref().map(|normals| corners(normals, i)),
+            uvs: uvs.as_ref().map(|uvs| corners(uvs, i)),
+        })
+        .collect())
+}
+
+/// What an MTL material is turned into
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Light,
+    Glass,
+    Metal,
+    Diffuse,
+}
+
+struct MtlDesc {
+    /// Line of the `newmtl` statement
+    line: usize,
+    diffuse: Color,
+    specular: Color,
+    emission: Color,
+    specular_exponent: f64,
+    index_of_refraction: f64,
+    dissolve: f64,
+    /// Path and line of `map_Kd`
+    diffuse_map: Option<(PathBuf, usize)>,
+}
+
+impl MtlDesc {
+    fn new(line: usize) -> Self {
+        Self {
+            line,
+            diffuse: Color::new([0.8, 0.8, 0.8]),
+            specular: Color::new([0.0, 0.0, 0.0]),
+            emission: Color::new([0.0, 0.0, 0.0]),
+            specular_exponent: 0.0,
+            index_of_refraction: 1.5,
+            dissolve: 1.0,
+            diffuse_map: None,
+        }
+    }
+
+    fn kind(&self) -> Kind {
+        let brightest = |color: &Color| color.x().max(color.y()).max(color.z());
+        if brightest(&self.emission) > 0.0 {
+            Kind::Light
+        } else if self.dissolve < 1.0 {
+            Kind::Glass
+        } else if brightest(&self.specular) > brightest(&self.diffuse) {
+            Kind::Metal
+        } else {
+            Kind::Diffuse
+        }
+    }
+
+    fn build(&self, path: &Path) -> Result<Arc<dyn Material>, ObjError> {
+        Ok(match self.kind() {
+            Kind::Light => Arc::new(DiffuseLight::new(self.emission)),
+            Kind::Glass => Arc::new(Dielectric::new(self.index_of_refraction)),
+            Kind::Metal => {
+                // Rough match of a Phong exponent to the width of the lobe
+                let fuzz = (2.0 / (self.specular_exponent + 2.0)).sqrt().min(1.0);
+                Arc::new(Metal::new(self.specular, fuzz))
+            }
+            Kind::Diffuse => match &self.diffuse_map {
+                Some((map, line)) => {
+                    let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+                    let texture = ImageTexture::load(&base_dir.join(map), WrapMode::Repeat)
+                        .map_err(|err| Location { path, line: *line }.error(err.to_string()))?;
+                    Arc::new(Lambertian::with_texture(Arc::new(texture)))
+                }
+                None => Arc::new(Lambertian::new(self.diffuse)),
+            },
+        })
+    }
+}
+
+/// Parses an MTL library read from `path`, texture maps are loaded relative
+/// to it
+fn parse_mtl(source: &str, path: &Path) -> Result<HashMap<String, Arc<dyn Material>>, ObjError> {
+    let mut descs: Vec<(String, MtlDesc)> = vec![];
+
+    for (number, line) in source.lines().enumerate() {
+        let location = Location {
+            path,
+            line: number + 1,
+        };
+        let line = line.split('#').next().unwrap_or_default();
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        if keyword == "newmtl" {
+            let name = location.rest(line, keyword)?;
+            descs.push((name.to_string(), MtlDesc::new(location.line)));
+            continue;
+        }
+        let desc = match descs.last_mut() {
+            Some((_, desc)) => desc,
+            None => return Err(location.error(format!("'{}' before 'newmtl'", keyword))),
+        };
+        match keyword {
+            "Kd" => desc.diffuse = location.color(&args)?,
+            "Ks" => desc.specular = location.color(&args)?,
+            "Ke" => desc.emission = location.color(&args)?,
+            "Ns" => desc.specular_exponent = location.float(&args)?.max(0.0),
+            "Ni" => {
+                let index_of_refraction = location.float(&args)?;
+                if index_of_refraction <= 0.0 {
+                    return Err(location.error(format!(
+                        "index of refraction must be positive, got {}",
+                        index_of_refraction
+                    )));
+                }
+                desc.index_of_refraction = index_of_refraction;
+            }
+            "d" => desc.dissolve = location.float(&args)?,
+            "Tr" => desc.dissolve = 1.0 - location.float(&args)?,
+            "map_Kd" => {
+                let map = location.rest(line, keyword)?;
+                if map.starts_with('-') {
+                    return Err(location.error("texture map options are not supported".to_string()));
+                }
+                desc.diffuse_map = Some((PathBuf::from(map), location.line));
+            }
+            _ => {}
+        }
+    }
+
+    let mut materials = HashMap::new();
+    for (name, desc) in descs {
+        if materials.contains_key(&name) {
+            return Err(Location {
+                path,
+                line: desc.line,
+            }
+            .error(format!("material '{}' is defined twice", name)));
+        }
+        let material = desc.build(path)?;
+        materials.insert(name, material);
+    }
+    Ok(materials)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Kind, MtlDesc, ObjError};
+    use crate::{
+        color::Color, hittable::Hittable, material::Lambertian, point::Point, ray::Ray, utils::Rng,
+        vec3::Vec3,
+    };
+    use rand::SeedableRng;
+    use std::{path::Path, sync::Arc};
+
+    fn parse(source: &str) -> Result<crate::hittable::HittableList, ObjError> {
+        let material = Arc::new(Lambertian::new(Color::new([0.5, 0.5, 0.5])));
+        super::parse(source, Path::new("model.obj"), material)
+    }
+
+    fn error_line(result: Result<impl Sized, ObjError>) -> usize {
+        match result {
+            Err(ObjError::Invalid { line, .. }) => line,
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("should not parse"),
+        }
+    }
+
+    const QUAD: &str = "
+# A unit quad facing +z
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vn 0 0 1
+f 1/1/1 2/2/1 3/3/1 -1/-1/-1
+";
+
+    #[test]
+    fn triangulates_polygons() {
+        let mut rng = Rng::seed_from_u64(0);
+        let model = parse(QUAD).unwrap();
+        for (x, y) in [(0.75, 0.25), (0.25, 0.75)] {
+            let ray = Ray::new(Point::new([x, y, 1.0]), Vec3::new([0.0, 0.0, -1.0]), 0.0);
+            let hit = model.hit(&ray, 0.001, f64::INFINITY, &mut rng).unwrap();
+            assert!(hit.front_face);
+            assert_eq!(hit.normal, Vec3::new([0.0, 0.0, 1.0]));
+            assert!((hit.u - x).abs() < 1e-12 && (hit.v - y).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn errors_have_line_numbers() {
+        assert_eq!(error_line(parse(&QUAD.replace("v 1 1 0", "v 1 x 0"))), 5);
+        assert_eq!(error_line(parse(&QUAD.replace("vn 0 0 1", "vn 0 0"))), 11);
+        assert_eq!(error_line(parse(&format!("{}f 1 2 5\n", QUAD))), 13);
+        assert_eq!(error_line(parse(&format!("{}f 1 2\n", QUAD))), 13);
+        assert_eq!(error_line(parse(&format!("{}f 1 0 2\n", QUAD))), 13);
+        assert_eq!(error_line(parse(&format!("{}usemtl red\n", QUAD))), 13);
+        assert_eq!(error_line(parse("v 0 0 0\n")), 1);
+        assert!(matches!(
+            parse("mtllib does/not/exist.mtl\n"),
+            Err(ObjError::Io { .. })
+        ));
+    }
+
+    #[test]
+    fn material_kinds() {
+        let materials = super::parse_mtl(
+            "newmtl matte
+Kd 0.8 0.1 0.1
+
+newmtl chrome
+Kd 0.1 0.1 0.1
+Ks 0.9 0.9 0.9
+Ns 500
+
+newmtl glass
+Ni 1.45
+d 0.1
+
+newmtl lamp
+Ke 10 10 10
+",
+            Path::new("model.mtl"),
+        )
+        .unwrap();
+        assert_eq!(materials.len(), 4);
+
+        let kind = |setup: fn(&mut MtlDesc)| {
+            let mut desc = MtlDesc::new(1);
+            setup(&mut desc);
+            desc.kind()
+        };
+        assert_eq!(kind(|_| {}), Kind::Diffuse);
+        assert_eq!(
+            kind(|desc| desc.specular = Color::new([0.9, 0.9, 0.9])),
+            Kind::Metal
+        );
+        assert_eq!(kind(|desc| desc.dissolve = 0.5), Kind::Glass);
+        assert_eq!(
+            kind(|desc| desc.emission = Color::new([1.0, 0.0, 0.0])),
+            Kind::Light
+        );
+
+        let path = Path::new("model.mtl");
+        assert_eq!(error_line(super::parse_mtl("Kd 1 1 1\n", path)), 1);
+        assert_eq!(
+            error_line(super::parse_mtl("newmtl a\nKd 1 -1 1\n", path)),
+            2
+        );
+        assert_eq!(error_line(super::parse_mtl("newmtl a\nNi 0\n", path)), 2);
+        assert_eq!(
+            error_line(super::parse_mtl("newmtl a\n\nnewmtl a\n", path)),
+            3
+        );
+    }
+}
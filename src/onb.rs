@@ -0,0 +1,59 @@
+use crate::vec3::Vec3;
+
+/// Orthonormal basis with `w` along a given direction, used to turn
+/// directions sampled around the z axis into directions around a normal
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Onb {
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+}
+
+impl Onb {
+    pub(crate) fn from_w(w: &Vec3) -> Self {
+        let w = w.as_unit_vec();
+        // Any axis not (almost) parallel to w works as a starting point
+        let a = if w.x().abs() > 0.9 {
+            Vec3::new([0.0, 1.0, 0.0])
+        } else {
+            Vec3::new([1.0, 0.0, 0.0])
+        };
+        let v = w.cross(&a).as_unit_vec();
+        let u = v.cross(&w);
+        Self { u, v, w }
+    }
+
+    pub(crate) fn w(&self) -> Vec3 {
+        self.w
+    }
+
+    /// The vector with coordinates `a` in this basis
+    pub(crate) fn local(&self, a: &Vec3) -> Vec3 {
+        a.x() * &self.u + a.y() * &self.v + a.z() * &self.w
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Onb;
+    use crate::vec3::Vec3;
+
+    #[test]
+    fn orthonormal() {
+        for w in [
+            Vec3::new([0.0, 0.0, 2.0]),
+            Vec3::new([-1.0, 0.0, 0.0]),
+            Vec3::new([1.0, 2.0, -3.0]),
+        ] {
+            let onb = Onb::from_w(&w);
+            for (a, b) in [(onb.u, onb.v), (onb.v, onb.w), (onb.w, onb.u)] {
+                assert!(a.dot(&b).abs() < 1e-12);
+                assert!((a.len() - 1.0).abs() < 1e-12);
+            }
+            let z = onb.local(&Vec3::new([0.0, 0.0, 1.0]));
+            assert!((z - w.as_unit_vec()).len() < 1e-12);
+            // Right-handed, so sampled directions keep their orientation
+            assert!((onb.u.cross(&onb.v) - onb.w).len() < 1e-12);
+        }
+    }
+}
@@ -0,0 +1,559 @@
+use crate::{
+    aov::{Aov, FirstHit},
+    color::Color,
+    render::Framebuffer,
+    tonemap::ToneMapping,
+};
+use clap::ValueEnum;
+use exr::prelude::{
+    f16, AnyChannel, AnyChannels, Compression, Encoding, FlatSamples, Image, ImageAttributes,
+    IntegerBounds, Layer, LayerAttributes, SmallVec, SpecificChannels, Vec2, WritableImage,
+};
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Seek, Write},
+    path::Path,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ImageFormat {
+    Png,
+    Ppm,
+    /// OpenEXR, linear floating point
+    Exr,
+    /// Portable float map, linear 32-bit floats
+    Pfm,
+}
+
+impl ImageFormat {
+    pub(crate) fn from_path(path: &Path) -> Result<Self, OutputError> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+
+        match extension.as_deref() {
+            Some("png") => Ok(Self::Png),
+            Some("ppm") => Ok(Self::Ppm),
+            Some("exr") => Ok(Self::Exr),
+            Some("pfm") => Ok(Self::Pfm),
+            _ => Err(OutputError::UnsupportedFormat(path.display().to_string())),
+        }
+    }
+
+    /// Whether the format keeps linear radiance, including values above 1
+    pub(crate) fn is_hdr(self) -> bool {
+        matches!(self, Self::Exr | Self::Pfm)
+    }
+}
+
+/// Sample type of EXR channels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum ExrPrecision {
+    /// 16-bit floats, plenty for color and half the size
+    Half,
+    /// 32-bit floats
+    Float,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum ExrCompression {
+    None,
+    /// Lossless zlib compression of blocks of 16 scanlines
+    Zip,
+}
+
+/// How images are encoded, beyond what the file extension says
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct OutputSettings {
+    /// Display transform of PNG and PPM images
+    pub(crate) tone_mapping: ToneMapping,
+    pub(crate) exr_precision: ExrPrecision,
+    pub(crate) exr_compression: ExrCompression,
+}
+
+#[derive(Debug)]
+pub(crate) enum OutputError {
+    UnsupportedFormat(String),
+    Io(io::Error),
+    Encoding(image::ImageError),
+    Exr(exr::error::Error),
+}
+
+impl fmt::Display for OutputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedFormat(path) => write!(
+                f,
+                "cannot tell the image format of '{}', use a .png, .ppm, .exr or .pfm extension",
+                path
+            ),
+            Self::Io(err) => write!(f, "failed to write image: {}", err),
+            Self::Encoding(err) => write!(f, "failed to encode image: {}", err),
+            Self::Exr(err) => write!(f, "failed to write EXR image: {}", err),
+        }
+    }
+}
+
+impl From<io::Error> for OutputError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<image::ImageError> for OutputError {
+    fn from(err: image::ImageError) -> Self {
+        Self::Encoding(err)
+    }
+}
+
+impl From<exr::error::Error> for OutputError {
+    fn from(err: exr::error::Error) -> Self {
+        Self::Exr(err)
+    }
+}
+
+/// Writes the average `colors` of an image `width` pixels wide to `path`,
+/// picking the format from its extension. HDR formats get the linear
+/// radiance, the others are tone mapped and sRGB encoded to 8 bits.
+pub(crate) fn write_image(
+    path: &Path,
+    width: usize,
+    colors: &[Color],
+    settings: &OutputSettings,
+) -> Result<(), OutputError> {
+    write_buffer(path, width, colors, settings, |color| {
+        settings.tone_mapping.apply(color).to_rgb8()
+    })
+}
+
+/// Writes how many samples every pixel took, from black for none through
+/// purple and orange to pale yellow for `max_samples`. HDR formats get the
+/// sample counts themselves.
+pub(crate) fn write_sample_heatmap(
+    path: &Path,
+    framebuffer: &Framebuffer,
+    max_samples: u32,
+    settings: &OutputSettings,
+) -> Result<(), OutputError> {
+    let counts: Vec<Color> = framebuffer
+        .samples()
+        .map(|samples| Color::new([samples as f64; 3]))
+        .collect();
+    write_buffer(path, framebuffer.width, &counts, settings, |count| {
+        heat(count.x() / max_samples as f64)
+    })
+}
+
+/// Writes one AOV of the averaged `first_hits`
+pub(crate) fn write_aov(
+    path: &Path,
+    aov: Aov,
+    width: usize,
+    first_hits: &[FirstHit],
+    settings: &OutputSettings,
+) -> Result<(), OutputError> {
+    let values: Vec<Color> = first_hits
+        .iter()
+        .map(|first_hit| aov.value(first_hit))
+        .collect();
+    let range = values
+        .iter()
+        .fold((values[0], values[0]), |(min, max), value| {
+            (min.min(value), max.max(value))
+        });
+    write_buffer(path, width, &values, settings, |value| {
+        aov.to_rgb8(value, range)
+    })
+}
+
+/// Writes the image and every AOV as the layers of one EXR file
+pub(crate) fn write_aov_layers(
+    path: &Path,
+    width: usize,
+    colors: &[Color],
+    first_hits: &[FirstHit],
+    settings: &OutputSettings,
+) -> Result<(), OutputError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_exr_layers(&mut writer, width, colors, first_hits, settings)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes `values` as they are to HDR formats, or converted by `to_rgb8`
+fn write_buffer(
+    path: &Path,
+    width: usize,
+    values: &[Color],
+    settings: &OutputSettings,
+    to_rgb8: impl Fn(Color) -> [u8; 3],
+) -> Result<(), OutputError> {
+    let format = ImageFormat::from_path(path)?;
+    if format.is_hdr() {
+        return save_hdr(path, format, width, values, settings);
+    }
+
+    let pixels: Vec<u8> = values.iter().flat_map(|&value| to_rgb8(value)).collect();
+    save_rgb8(path, format, width, values.len() / width, &pixels)
+}
+
+/// Color of `t` between 0 and 1 on the heatmap scale
+fn heat(t: f64) -> [u8; 3] {
+    const STOPS: [[f64; 3]; 4] = [
+        [0.0, 0.0, 0.0],
+        [0.5, 0.0, 0.6],
+        [1.0, 0.45, 0.0],
+        [1.0, 1.0, 0.75],
+    ];
+    let position = t.clamp(0.0, 1.0) * (STOPS.len() - 1) as f64;
+    let index = (position as usize).min(STOPS.len() - 2);
+    let fraction = position - index as f64;
+    let (from, to) = (STOPS[index], STOPS[index + 1]);
+    [0, 1, 2].map(|c| (255.0 * (from[c] + (to[c] - from[c]) * fraction)).round() as u8)
+}
+
+fn save_rgb8(
+    path: &Path,
+    format: ImageFormat,
+    width: usize,
+    height: usize,
+    pixels: &[u8],
+) -> Result<(), OutputError> {
+    match format {
+        ImageFormat::Png => image::save_buffer_with_format(
+            path,
+            pixels,
+            width as u32,
+            height as u32,
+            image::ExtendedColorType::Rgb8,
+            image::ImageFormat::Png,
+        )?,
+        ImageFormat::Ppm => {
+            let mut writer = BufWriter::new(File::create(path)?);
+            write_ppm(&mut writer, width, height, pixels)?;
+            writer.flush()?;
+        }
+        ImageFormat::Exr | ImageFormat::Pfm => unreachable!("{:?} is not an 8-bit format", format),
+    }
+    Ok(())
+}
+
+/// Writes linear colors, stored row by row from the top
+fn save_hdr(
+    path: &Path,
+    format: ImageFormat,
+    width: usize,
+    colors: &[Color],
+    settings: &OutputSettings,
+) -> Result<(), OutputError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    match format {
+        ImageFormat::Exr => write_exr(&mut writer, width, colors, settings)?,
+        ImageFormat::Pfm => write_pfm(&mut writer, width, colors)?,
+        ImageFormat::Png | ImageFormat::Ppm => {
+            unreachable!("{:?} is not an HDR format", format)
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn exr_encoding(settings: &OutputSettings) -> Encoding {
+    Encoding {
+        compression: match settings.exr_compression {
+            ExrCompression::None => Compression::Uncompressed,
+            ExrCompression::Zip => Compression::ZIP16,
+        },
+        ..Encoding::default()
+    }
+}
+
+/// Single layer RGB OpenEXR image
+fn write_exr(
+    writer: impl Write + Seek,
+    width: usize,
+    colors: &[Color],
+    settings: &OutputSettings,
+) -> Result<(), exr::error::Error> {
+    let size = (width, colors.len() / width);
+    let encoding = exr_encoding(settings);
+    let color = |Vec2(x, y): Vec2<usize>| colors[y * width + x];
+
+    match settings.exr_precision {
+        ExrPrecision::Half => {
+            let channels = SpecificChannels::rgb(|position| {
+                let [r, g, b] = [0, 1, 2].map(|c| f16::from_f64(color(position)[c]));
+                (r, g, b)
+            });
+            let layer = Layer::new(size, LayerAttributes::default(), encoding, channels);
+            Image::from_layer(layer).write().to_buffered(writer)
+        }
+        ExrPrecision::Float => {
+            let channels = SpecificChannels::rgb(|position| {
+                let [r, g, b] = [0, 1, 2].map(|c| color(position)[c] as f32);
+                (r, g, b)
+            });
+            let layer = Layer::new(size, LayerAttributes::default(), encoding, channels);
+            Image::from_layer(layer).write().to_buffered(writer)
+        }
+    }
+}
+
+/// Multi-layer OpenEXR image with the radiance in a layer called `beauty`
+/// and a layer for every AOV, named like its `--aov` kind. IDs are stored as
+/// unsigned integers, everything else in the configured precision.
+fn write_exr_layers(
+    writer: impl Write + Seek,
+    width: usize,
+    colors: &[Color],
+    first_hits: &[FirstHit],
+    settings: &OutputSettings,
+) -> Result<(), exr::error::Error> {
+    let size = (width, colors.len() / width);
+    let encoding = exr_encoding(settings);
+    let floats = |values: Vec<f64>| match settings.exr_precision {
+        ExrPrecision::Half => FlatSamples::F16(values.into_iter().map(f16::from_f64).collect()),
+        ExrPrecision::Float => FlatSamples::F32(values.into_iter().map(|v| v as f32).collect()),
+    };
+    let layer = |name: &str, channels: Vec<AnyChannel<FlatSamples>>| {
+        Layer::new(
+            size,
+            LayerAttributes::named(name),
+            encoding,
+            AnyChannels::sort(SmallVec::from_vec(channels)),
+        )
+    };
+
+    let color_channels = |names: &[&str], values: &[Color]| {
+        names
+            .iter()
+            .enumerate()
+            .map(|(c, &name)| AnyChannel::new(name, floats(values.iter().map(|v| v[c]).collect())))
+            .collect()
+    };
+    let mut layers = vec![layer("beauty", color_channels(&["R", "G", "B"], colors))];
+    for aov in Aov::value_variants() {
+        let channels = if aov.is_id() {
+            let ids = first_hits
+                .iter()
+                .map(|first_hit| aov.id(first_hit))
+                .collect();
+            vec![AnyChannel::new("id", FlatSamples::U32(ids))]
+        } else {
+            let values: Vec<Color> = first_hits.iter().map(|hit| aov.value(hit)).collect();
+            color_channels(aov.channels(), &values)
+        };
+        let name = aov.to_possible_value().expect("no AOV is skipped");
+        layers.push(layer(name.get_name(), channels));
+    }
+
+    let attributes = ImageAttributes::new(IntegerBounds::from_dimensions(size));
+    Image::from_layers(attributes, layers)
+        .write()
+        .to_buffered(writer)
+}
+
+/// Color PFM. Its rows go from the bottom up, a negative scale marks
+/// little-endian floats.
+fn write_pfm(writer: &mut impl Write, width: usize, colors: &[Color]) -> io::Result<()> {
+    let height = colors.len() / width;
+    write!(writer, "PF\n{} {}\n-1.0\n", width, height)?;
+    for row in colors.chunks(width).rev() {
+        for color in row {
+            for c in 0..3 {
+                writer.write_all(&(color[c] as f32).to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Binary (P6) PPM, which every image viewer understands
+fn write_ppm(
+    writer: &mut impl Write,
+    width: usize,
+    height: usize,
+    pixels: &[u8],
+) -> io::Result<()> {
+    write!(writer, "P6\n{} {}\n255\n", width, height)?;
+    writer.write_all(pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        heat, write_exr, write_exr_layers, write_pfm, write_ppm, ExrCompression, ExrPrecision,
+        ImageFormat, OutputSettings,
+    };
+    use crate::{
+        aov::FirstHit,
+        color::Color,
+        material::MaterialKind,
+        tonemap::{ToneMapper, ToneMapping},
+    };
+    use exr::prelude::{read, FlatSamples, ReadChannels, ReadLayers};
+    use std::{io::Cursor, path::Path};
+
+    #[test]
+    fn format_from_extension() {
+        assert_eq!(
+            ImageFormat::from_path(Path::new("image.png")).unwrap(),
+            ImageFormat::Png
+        );
+        assert_eq!(
+            ImageFormat::from_path(Path::new("out/IMAGE.PPM")).unwrap(),
+            ImageFormat::Ppm
+        );
+        assert_eq!(
+            ImageFormat::from_path(Path::new("render.exr")).unwrap(),
+            ImageFormat::Exr
+        );
+        assert!(ImageFormat::from_path(Path::new("render.pfm"))
+            .unwrap()
+            .is_hdr());
+        assert!(ImageFormat::from_path(Path::new("image.jpg")).is_err());
+        assert!(ImageFormat::from_path(Path::new("image")).is_err());
+    }
+
+    #[test]
+    fn ppm_header() {
+        let mut buffer = vec![];
+        write_ppm(&mut buffer, 2, 1, &[1, 2, 3, 4, 5, 6]).unwrap();
+        assert_eq!(buffer, b"P6\n2 1\n255\n\x01\x02\x03\x04\x05\x06");
+    }
+
+    #[test]
+    fn heatmap_scale() {
+        assert_eq!(heat(0.0), [0, 0, 0]);
+        assert_eq!(heat(1.0), [255, 255, 191]);
+        assert_eq!(heat(2.0), heat(1.0));
+        // Brighter with every step
+        let sums: Vec<u32> = (0..=10)
+            .map(|i| heat(i as f64 / 10.0).iter().map(|&c| c as u32).sum())
+            .collect();
+        assert!(sums.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    fn two_rows() -> Vec<Color> {
+        vec![
+            Color::new([12.5, 0.25, 0.0]),
+            Color::new([1.0, 2.0, 3.0]),
+            Color::new([0.5, 0.5, 0.5]),
+            Color::new([0.0, 0.0, 100.0]),
+        ]
+    }
+
+    #[test]
+    fn pfm_layout() {
+        let mut buffer = vec![];
+        write_pfm(&mut buffer, 2, &two_rows()).unwrap();
+        let header = b"PF\n2 2\n-1.0\n";
+        assert_eq!(&buffer[..header.len()], header);
+
+        let floats: Vec<f32> = buffer[header.len()..]
+            .chunks(4)
+            .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .collect();
+        // The bottom row comes first
+        assert_eq!(floats.len(), 12);
+        assert_eq!(&floats[..3], &[0.5, 0.5, 0.5]);
+        assert_eq!(&floats[6..9], &[12.5, 0.25, 0.0]);
+    }
+
+    fn settings(exr_precision: ExrPrecision, exr_compression: ExrCompression) -> OutputSettings {
+        OutputSettings {
+            tone_mapping: ToneMapping {
+                operator: ToneMapper::Clamp,
+                exposure: 0.0,
+                white_point: 1.0,
+            },
+            exr_precision,
+            exr_compression,
+        }
+    }
+
+    #[test]
+    fn exr_round_trip() {
+        for exr_precision in [ExrPrecision::Half, ExrPrecision::Float] {
+            for exr_compression in [ExrCompression::None, ExrCompression::Zip] {
+                let settings = settings(exr_precision, exr_compression);
+                let mut buffer = Cursor::new(vec![]);
+                write_exr(&mut buffer, 2, &two_rows(), &settings).unwrap();
+
+                buffer.set_position(0);
+                let image = read()
+                    .no_deep_data()
+                    .largest_resolution_level()
+                    .all_channels()
+                    .first_valid_layer()
+                    .all_attributes()
+                    .from_buffered(buffer)
+                    .unwrap();
+                let layer = image.layer_data;
+                assert_eq!((layer.size.0, layer.size.1), (2, 2));
+
+                // Channels are sorted by name, B, G, R
+                let channels = &layer.channel_data.list;
+                let red = &channels[2];
+                assert!(red.name == *"R");
+                match (&red.sample_data, exr_precision) {
+                    (FlatSamples::F16(_), ExrPrecision::Half)
+                    | (FlatSamples::F32(_), ExrPrecision::Float) => {}
+                    (samples, precision) => panic!("{:?} for {:?}", samples, precision),
+                }
+                let red: Vec<f32> = red.sample_data.values_as_f32().collect();
+                let blue: Vec<f32> = channels[0].sample_data.values_as_f32().collect();
+                assert_eq!(red, vec![12.5, 1.0, 0.5, 0.0]);
+                assert_eq!(blue, vec![0.0, 3.0, 0.5, 100.0]);
+            }
+        }
+    }
+
+    #[test]
+    fn exr_layers() {
+        let first_hits: Vec<FirstHit> = (0..4)
+            .map(|i| FirstHit {
+                depth: i as f64,
+                object_id: i,
+                material: Some(MaterialKind::Dielectric),
+                ..FirstHit::zero()
+            })
+            .collect();
+        let mut buffer = Cursor::new(vec![]);
+        let settings = settings(ExrPrecision::Float, ExrCompression::Zip);
+        write_exr_layers(&mut buffer, 2, &two_rows(), &first_hits, &settings).unwrap();
+
+        buffer.set_position(0);
+        let image = read()
+            .no_deep_data()
+            .largest_resolution_level()
+            .all_channels()
+            .all_layers()
+            .all_attributes()
+            .from_buffered(buffer)
+            .unwrap();
+        let names: Vec<String> = image
+            .layer_data
+            .iter()
+            .map(|layer| layer.attributes.layer_name.as_ref().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            names,
+            ["beauty", "albedo", "normal", "depth", "position", "object", "material"]
+        );
+
+        let channel = |layer: usize| &image.layer_data[layer].channel_data.list[0];
+        let depth: Vec<f32> = channel(3).sample_data.values_as_f32().collect();
+        assert_eq!(depth, vec![0.0, 1.0, 2.0, 3.0]);
+        match &channel(5).sample_data {
+            FlatSamples::U32(ids) => assert_eq!(ids, &vec![0, 1, 2, 3]),
+            samples => panic!("{:?}", samples),
+        }
+        match &channel(6).sample_data {
+            FlatSamples::U32(ids) => assert_eq!(ids, &vec![3; 4]),
+            samples => panic!("{:?}", samples),
+        }
+    }
+}
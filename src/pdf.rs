@@ -0,0 +1,212 @@
+use crate::{
+    hittable::Hittable,
+    onb::Onb,
+    point::Point,
+    utils::{self, Rng},
+    vec3::Vec3,
+};
+use std::f64::consts::PI;
+
+/// Probability density over directions, which can also draw directions
+/// distributed by itself
+pub(crate) trait Pdf {
+    /// Density of `direction`, with respect to solid angle
+    fn value(&self, direction: &Vec3, rng: &mut Rng) -> f64;
+
+    fn generate(&self, rng: &mut Rng) -> Vec3;
+}
+
+/// Directions in the hemisphere around a normal, weighted by the cosine to it
+pub(crate) struct CosinePdf {
+    uvw: Onb,
+}
+
+impl CosinePdf {
+    pub(crate) fn new(normal: &Vec3) -> Self {
+        Self {
+            uvw: Onb::from_w(normal),
+        }
+    }
+}
+
+impl Pdf for CosinePdf {
+    fn value(&self, direction: &Vec3, _rng: &mut Rng) -> f64 {
+        let cosine = direction.as_unit_vec().dot(&self.uvw.w());
+        (cosine / PI).max(0.0)
+    }
+
+    fn generate(&self, rng: &mut Rng) -> Vec3 {
+        self.uvw.local(&random_cosine_direction(rng))
+    }
+}
+
+/// Every direction equally likely
+pub(crate) struct SpherePdf;
+
+impl Pdf for SpherePdf {
+    fn value(&self, _direction: &Vec3, _rng: &mut Rng) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+
+    fn generate(&self, rng: &mut Rng) -> Vec3 {
+        Vec3::random_unit_vec(rng)
+    }
+}
+
+/// Directions from `origin` towards the objects, usually the lights
+pub(crate) struct HittablePdf<'a> {
+    objects: &'a dyn Hittable,
+    origin: Point,
+}
+
+impl<'a> HittablePdf<'a> {
+    pub(crate) fn new(objects: &'a dyn Hittable, origin: Point) -> Self {
+        Self { objects, origin }
+    }
+}
+
+impl Pdf for HittablePdf<'_> {
+    fn value(&self, direction: &Vec3, rng: &mut Rng) -> f64 {
+        self.objects.pdf_value(&self.origin, direction, rng)
+    }
+
+    fn generate(&self, rng: &mut Rng) -> Vec3 {
+        self.objects.random(&self.origin, rng)
+    }
+}
+
+/// Even mix of two densities, good wherever either of them is
+pub(crate) struct MixturePdf<'a> {
+    pdfs: [&'a dyn Pdf; 2],
+}
+
+impl<'a> MixturePdf<'a> {
+    pub(crate) fn new(first: &'a dyn Pdf, second: &'a dyn Pdf) -> Self {
+        Self {
+            pdfs: [first, second],
+        }
+    }
+}
+
+impl Pdf for MixturePdf<'_> {
+    fn value(&self, direction: &Vec3, rng: &mut Rng) -> f64 {
+        0.5 * self.pdfs[0].value(direction, rng) + 0.5 * self.pdfs[1].value(direction, rng)
+    }
+
+    fn generate(&self, rng: &mut Rng) -> Vec3 {
+        if utils::random_float(rng) < 0.5 {
+            self.pdfs[0].generate(rng)
+        } else {
+            self.pdfs[1].generate(rng)
+        }
+    }
+}
+
+/// Unit vector around the z axis with density `cos(theta) / pi`
+fn random_cosine_direction(rng: &mut Rng) -> Vec3 {
+    let r_1 = utils::random_float(rng);
+    let r_2 = utils::random_float(rng);
+    let phi = 2.0 * PI * r_1;
+    let (sin_phi, cos_phi) = phi.sin_cos();
+    Vec3::new([
+        cos_phi * r_2.sqrt(),
+        sin_phi * r_2.sqrt(),
+        (1.0 - r_2).sqrt(),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CosinePdf, HittablePdf, MixturePdf, Pdf, SpherePdf};
+    use crate::{
+        aarect::AaRect,
+        color::Color,
+        hittable::Hittable,
+        material::Lambertian,
+        point::Point,
+        sphere::Sphere,
+        transform::{Transform, Transformed},
+        triangle::{Face, Triangle, VertexBuffers},
+        utils::Rng,
+        vec3::Vec3,
+    };
+    use rand::SeedableRng;
+    use std::{f64::consts::PI, sync::Arc};
+
+    /// Monte Carlo estimate of the integral of `pdf` over all directions
+    fn integral(pdf: &dyn Pdf, rng: &mut Rng) -> f64 {
+        let runs = 100_000;
+        let sum: f64 = (0..runs)
+            .map(|_| {
+                let direction = Vec3::random_unit_vec(rng);
+                pdf.value(&direction, rng)
+            })
+            .sum();
+        4.0 * PI * sum / runs as f64
+    }
+
+    /// Generated directions must be where the density is
+    fn generates_own_directions(pdf: &dyn Pdf, rng: &mut Rng) {
+        for _ in 0..100 {
+            let direction = pdf.generate(rng);
+            assert!(pdf.value(&direction, rng) > 0.0, "{:?}", direction);
+        }
+    }
+
+    #[test]
+    fn densities_integrate_to_one() {
+        let mut rng = Rng::seed_from_u64(0);
+        let material = Arc::new(Lambertian::new(Color::new([0.5, 0.5, 0.5])));
+        let origin = Point::new([0.0, 0.0, 0.0]);
+
+        let rect = AaRect::xz((-1.0, 1.0), (-2.0, 0.5), 1.0, material.clone());
+        let sphere = Sphere::new(Point::new([0.0, 0.0, -3.0]), 1.0, material.clone());
+        let inside = Sphere::new(Point::new([0.5, 0.0, 0.0]), 1.0, material.clone());
+        let vertices = VertexBuffers {
+            positions: vec![
+                Point::new([-1.0, -1.0, 2.0]),
+                Point::new([1.0, -1.0, 2.0]),
+                Point::new([0.0, 1.0, 1.5]),
+            ],
+            ..VertexBuffers::default()
+        };
+        let triangle = Triangle::new(Arc::new(vertices), Face::new([0, 1, 2]), material.clone());
+        // Non-uniform scaling changes solid angles unevenly
+        let transform = Transform::scaling(Vec3::new([3.0, 1.0, 0.5]))
+            .then(&Transform::rotation(Vec3::new([1.0, 1.0, 0.0]), 30.0))
+            .then(&Transform::translation(Vec3::new([0.0, -2.0, 0.0])));
+        let ellipsoid = Transformed::new(
+            Arc::new(Sphere::new(Point::new([0.0, 0.0, 0.0]), 1.0, material)),
+            transform,
+        );
+
+        let objects: [&dyn Hittable; 5] = [&rect, &sphere, &inside, &triangle, &ellipsoid];
+        for object in objects {
+            let pdf = HittablePdf::new(object, origin);
+            assert!((integral(&pdf, &mut rng) - 1.0).abs() < 0.03);
+            generates_own_directions(&pdf, &mut rng);
+        }
+
+        let cosine = CosinePdf::new(&Vec3::new([1.0, -1.0, 0.0]));
+        let mixture = MixturePdf::new(&cosine, &SpherePdf);
+        for pdf in [&cosine as &dyn Pdf, &SpherePdf, &mixture] {
+            assert!((integral(pdf, &mut rng) - 1.0).abs() < 0.03);
+            generates_own_directions(pdf, &mut rng);
+        }
+    }
+
+    #[test]
+    fn cosine_weighted() {
+        let mut rng = Rng::seed_from_u64(0);
+        let normal = Vec3::new([0.0, 2.0, 0.0]);
+        let pdf = CosinePdf::new(&normal);
+        // The mean of cos(theta) under a cosine density is 2/3
+        let runs = 100_000;
+        let mean = (0..runs)
+            .map(|_| pdf.generate(&mut rng).dot(&normal.as_unit_vec()))
+            .sum::<f64>()
+            / runs as f64;
+        assert!((mean - 2.0 / 3.0).abs() < 0.01);
+        assert_eq!(pdf.value(&-normal, &mut rng), 0.0);
+    }
+}
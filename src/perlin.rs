@@ -0,0 +1,143 @@
+use crate::{
+    point::Point,
+    utils::{self, Rng},
+    vec3::Vec3,
+};
+
+const POINT_COUNT: usize = 256;
+
+/// Gradient noise with random unit vectors on the lattice points, smoothed
+/// with a Hermite cubic and blended trilinearly between the eight corners
+pub(crate) struct Perlin {
+    gradients: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+impl Perlin {
+    pub(crate) fn new(rng: &mut Rng) -> Self {
+        let gradients = (0..POINT_COUNT)
+            .map(|_| Vec3::random(rng, -1.0, 1.0).as_unit_vec())
+            .collect();
+
+        Self {
+            gradients,
+            perm_x: Self::permutation(rng),
+            perm_y: Self::permutation(rng),
+            perm_z: Self::permutation(rng),
+        }
+    }
+
+    /// Fisher-Yates shuffle of `0..POINT_COUNT`
+    fn permutation(rng: &mut Rng) -> Vec<usize> {
+        let mut perm: Vec<usize> = (0..POINT_COUNT).collect();
+        for i in (1..POINT_COUNT).rev() {
+            let target = utils::random_range(rng, 0.0, (i + 1) as f64) as usize;
+            perm.swap(i, target);
+        }
+        perm
+    }
+
+    /// Noise value in roughly `[-1, 1]`
+    pub(crate) fn noise(&self, point: &Point) -> f64 {
+        let floor = [point.x().floor(), point.y().floor(), point.z().floor()];
+        let (u, v, w) = (
+            point.x() - floor[0],
+            point.y() - floor[1],
+            point.z() - floor[2],
+        );
+        let (i, j, k) = (floor[0] as i64, floor[1] as i64, floor[2] as i64);
+
+        let mut corners = [[[Vec3::new([0.0, 0.0, 0.0]); 2]; 2]; 2];
+        for (di, plane) in corners.iter_mut().enumerate() {
+            for (dj, row) in plane.iter_mut().enumerate() {
+                for (dk, corner) in row.iter_mut().enumerate() {
+                    let index = self.perm_x[Self::wrap(i + di as i64)]
+                        ^ self.perm_y[Self::wrap(j + dj as i64)]
+                        ^ self.perm_z[Self::wrap(k + dk as i64)];
+                    *corner = self.gradients[index];
+                }
+            }
+        }
+
+        Self::interpolate(&corners, u, v, w)
+    }
+
+    /// Sum of `depth` octaves, each at double the frequency and half the
+    /// weight of the previous one
+    pub(crate) fn turbulence(&self, point: &Point, depth: usize) -> f64 {
+        let mut accum = 0.0;
+        let mut temp = *point;
+        let mut weight = 1.0;
+
+        for _ in 0..depth {
+            accum += weight * self.noise(&temp);
+            weight *= 0.5;
+            temp = temp * 2.0;
+        }
+
+        accum.abs()
+    }
+
+    fn wrap(index: i64) -> usize {
+        (index & (POINT_COUNT as i64 - 1)) as usize
+    }
+
+    fn interpolate(corners: &[[[Vec3; 2]; 2]; 2], u: f64, v: f64, w: f64) -> f64 {
+        let hermite = |t: f64| t * t * (3.0 - 2.0 * t);
+        let (uu, vv, ww) = (hermite(u), hermite(v), hermite(w));
+
+        let mut accum = 0.0;
+        for (i, plane) in corners.iter().enumerate() {
+            for (j, row) in plane.iter().enumerate() {
+                for (k, gradient) in row.iter().enumerate() {
+                    let (fi, fj, fk) = (i as f64, j as f64, k as f64);
+                    let weight = Vec3::new([u - fi, v - fj, w - fk]);
+                    accum += (fi * uu + (1.0 - fi) * (1.0 - uu))
+                        * (fj * vv + (1.0 - fj) * (1.0 - vv))
+                        * (fk * ww + (1.0 - fk) * (1.0 - ww))
+                        * gradient.dot(&weight);
+                }
+            }
+        }
+        accum
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Perlin;
+    use crate::{point::Point, utils::Rng};
+    use rand::SeedableRng;
+
+    #[test]
+    fn seeded() {
+        let a = Perlin::new(&mut Rng::seed_from_u64(1));
+        let b = Perlin::new(&mut Rng::seed_from_u64(1));
+        let c = Perlin::new(&mut Rng::seed_from_u64(2));
+        let point = Point::new([0.3, 1.7, -4.2]);
+        assert_eq!(a.noise(&point), b.noise(&point));
+        assert_ne!(a.noise(&point), c.noise(&point));
+    }
+
+    #[test]
+    fn zero_on_lattice_and_bounded() {
+        let perlin = Perlin::new(&mut Rng::seed_from_u64(3));
+        assert_eq!(perlin.noise(&Point::new([2.0, -3.0, 7.0])), 0.0);
+
+        for i in 0..1000 {
+            let t = i as f64 * 0.137;
+            let value = perlin.noise(&Point::new([t, 0.5 * t, -t]));
+            assert!((-1.0..=1.0).contains(&value));
+        }
+    }
+
+    #[test]
+    fn continuous() {
+        let perlin = Perlin::new(&mut Rng::seed_from_u64(4));
+        let a = perlin.noise(&Point::new([1.0 - 1e-9, 0.5, 0.5]));
+        let b = perlin.noise(&Point::new([1.0 + 1e-9, 0.5, 0.5]));
+        assert!((a - b).abs() < 1e-6);
+    }
+}
@@ -1,56 +1,181 @@
-use crate::{color::Color, hittable::Hittable, point::Point, vec3::Vec3};
+use crate::{
+    background::Background,
+    color::Color,
+    hittable::Hittable,
+    integrator::{Bounces, OnFirstHit},
+    material::ScatterRecord,
+    pdf::{HittablePdf, MixturePdf, Pdf},
+    point::Point,
+    utils::Rng,
+    vec3::Vec3,
+};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub(crate) struct Ray {
     pub(crate) origin: Point,
     pub(crate) direction: Vec3,
+    /// When the ray was sent, between the camera's shutter open and close
+    pub(crate) time: f64,
 }
 
 impl Ray {
-    pub(crate) fn new(origin: Point, direction: Vec3) -> Self {
-        Ray { origin, direction }
+    pub(crate) fn new(origin: Point, direction: Vec3, time: f64) -> Self {
+        Ray {
+            origin,
+            direction,
+            time,
+        }
     }
 
     pub(crate) fn at(&self, t: f64) -> Point {
-        self.origin + &self.direction * t
+        self.origin + self.direction * t
     }
 
-    pub(crate) fn color(&self, world: &dyn Hittable, depth: u64) -> Color {
-        if depth <= 0 {
-            return Color::new([0.0, 0.0, 0.0]);
-        }
+    /// Radiance arriving along the ray. Diffuse bounces are sampled half
+    /// from the material and half towards `lights`, if there are any. What
+    /// the ray hits is also passed to `on_first_hit`, if given.
+    pub(crate) fn color(
+        &self,
+        world: &dyn Hittable,
+        lights: Option<&dyn Hittable>,
+        background: &dyn Background,
+        bounces: Bounces,
+        mut on_first_hit: Option<OnFirstHit<'_>>,
+        rng: &mut Rng,
+    ) -> Color {
+        let mut radiance = Color::new([0.0, 0.0, 0.0]);
+        // Share of the light found further along the path that reaches the
+        // camera
+        let mut throughput = Color::new([1.0, 1.0, 1.0]);
+        let mut ray = *self;
 
-        let hit_record = world.hit(self, 0.001, f64::INFINITY);
-        match hit_record {
-            Some(rec) => {
-                let scatter_res = rec.material.scatter(&self, &rec);
+        for depth in 0..bounces.max {
+            let hit = world.hit(&ray, 0.001, f64::INFINITY, rng);
+            if let Some(on_first_hit) = on_first_hit.take() {
+                on_first_hit(hit.as_ref());
+            }
+            let rec = match hit {
+                Some(rec) => rec,
+                None => {
+                    radiance = radiance + &throughput * &background.color(&ray);
+                    break;
+                }
+            };
+            radiance = radiance + &throughput * &rec.material.emitted(&ray, &rec);
 
-                match scatter_res {
-                    Some((scattered, attenuation)) => {
-                        &attenuation * &scattered.color(world, depth - 1)
+            let (scattered, weight) = match rec.material.scatter(&ray, &rec, rng) {
+                Some(ScatterRecord::Specular { ray, attenuation }) => (ray, attenuation),
+                Some(ScatterRecord::Diffuse { pdf, attenuation }) => {
+                    let (direction, pdf_value) = match lights {
+                        Some(lights) => {
+                            let light_pdf = HittablePdf::new(lights, rec.point);
+                            let mixture = MixturePdf::new(&light_pdf, pdf.as_ref());
+                            let direction = mixture.generate(rng);
+                            (direction, mixture.value(&direction, rng))
+                        }
+                        None => {
+                            let direction = pdf.generate(rng);
+                            (direction, pdf.value(&direction, rng))
+                        }
+                    };
+                    if pdf_value <= 0.0 {
+                        break;
                     }
-                    None => Color::new([0.0, 0.0, 0.0]),
+
+                    let scattered = Ray::new(rec.point, direction, ray.time);
+                    let scattering_pdf = rec.material.scattering_pdf(&ray, &rec, &scattered);
+                    (scattered, scattering_pdf / pdf_value * &attenuation)
                 }
+                None => break,
+            };
+
+            throughput = &throughput * &weight;
+            match bounces.roulette(depth + 1, &throughput, rng) {
+                Some(scale) => throughput = scale * &throughput,
+                None => break,
             }
-            None => {
-                let unit_direction = self.direction.as_unit_vec();
-                let t = 0.5 * (unit_direction.y() + 1.0);
-                Color::new([1.0, 1.0, 1.0]) * (1.0 - t) + Color::new([0.5, 0.7, 1.0]) * t
-            }
+            ray = scattered;
         }
+
+        radiance
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::Ray;
-    use crate::{point::Point, vec3::Vec3};
+    use crate::{
+        aarect::AaRect,
+        background::SolidColor,
+        color::Color,
+        hittable::{Hittable, HittableList},
+        integrator::Bounces,
+        material::{DiffuseLight, Lambertian},
+        point::Point,
+        utils::Rng,
+        vec3::Vec3,
+    };
+    use rand::SeedableRng;
+    use std::sync::Arc;
 
     #[test]
     fn at() {
         let origin = Point::new([0.0, 0.0, 0.0]);
         let direction = Vec3::new([1.0, 1.0, 0.0]);
-        let ray = Ray::new(origin, direction);
+        let ray = Ray::new(origin, direction, 0.0);
         assert_eq!(ray.at(0.5), Point::new([0.5, 0.5, 0.0]));
     }
+
+    #[test]
+    fn light_sampling_reduces_noise() {
+        let floor = Arc::new(AaRect::xz(
+            (-10.0, 10.0),
+            (-10.0, 10.0),
+            0.0,
+            Arc::new(Lambertian::new(Color::new([0.5, 0.5, 0.5]))),
+        ));
+        let light = Arc::new(
+            AaRect::xz(
+                (-0.5, 0.5),
+                (-0.5, 0.5),
+                1.0,
+                Arc::new(DiffuseLight::new(Color::new([4.0, 4.0, 4.0]))),
+            )
+            .flipped(),
+        );
+        let mut world = HittableList::new();
+        world.add(Box::new(floor));
+        world.add(Box::new(Arc::clone(&light)));
+        let background = SolidColor::new(Color::new([0.0, 0.0, 0.0]));
+
+        // Mean and variance of the light reflected by the floor below the lamp
+        let estimate = |lights: Option<&dyn Hittable>| {
+            let mut rng = Rng::seed_from_u64(0);
+            let ray = Ray::new(
+                Point::new([0.0, 0.5, 0.2]),
+                Vec3::new([0.0, -1.0, 0.0]),
+                0.0,
+            );
+            let bounces = Bounces {
+                max: 2,
+                roulette_after: 2,
+            };
+            let runs = 20_000;
+            let samples: Vec<f64> = (0..runs)
+                .map(|_| {
+                    ray.color(&world, lights, &background, bounces, None, &mut rng)
+                        .x()
+                })
+                .collect();
+            let mean = samples.iter().sum::<f64>() / runs as f64;
+            let variance =
+                samples.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / (runs - 1) as f64;
+            (mean, variance)
+        };
+
+        let (mean, variance) = estimate(None);
+        let (sampled_mean, sampled_variance) = estimate(Some(light.as_ref()));
+        assert!((sampled_mean - mean).abs() < 0.05 * mean);
+        assert!(sampled_variance < variance / 3.0);
+    }
 }
@@ -0,0 +1,507 @@
+use crate::{
+    aov::FirstHit,
+    background::Background,
+    camera::Camera,
+    color::Color,
+    hittable::{HitRecord, Hittable},
+    integrator::{self, Bounces, Heuristic, Integrator, OnFirstHit},
+    material::MaterialKind,
+    ray::Ray,
+    utils::{self, Rng},
+    vec3::Vec3,
+};
+use indicatif::ProgressBar;
+use rayon::prelude::*;
+use std::io::{self, Read, Write};
+
+/// Summed radiance and sample count of every pixel
+pub(crate) struct Framebuffer {
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    pixels: Vec<Pixel>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Pixel {
+    sum: Color,
+    stats: PixelStats,
+    /// Summed over the samples, if the renderer records first hits
+    first_hit: FirstHit,
+}
+
+impl Framebuffer {
+    pub(crate) fn new(width: usize, height: usize) -> Self {
+        let pixel = Pixel {
+            sum: Color::new([0.0, 0.0, 0.0]),
+            stats: PixelStats::default(),
+            first_hit: FirstHit::zero(),
+        };
+        Self {
+            width,
+            height,
+            pixels: vec![pixel; width * height],
+        }
+    }
+
+    /// Average color of every pixel. Rows are stored top to bottom, so the
+    /// first `width` colors are the top scanline of the image.
+    pub(crate) fn colors(&self) -> impl Iterator<Item = Color> + '_ {
+        self.pixels.iter().map(|pixel| match pixel.stats.count {
+            0 => Color::new([0.0, 0.0, 0.0]),
+            samples => pixel.sum / samples as f64,
+        })
+    }
+
+    /// Samples taken for every pixel, in the same order as `colors`
+    pub(crate) fn samples(&self) -> impl Iterator<Item = u32> + '_ {
+        self.pixels.iter().map(|pixel| pixel.stats.count)
+    }
+
+    /// Variance of the luminance of every pixel's average color, zero for
+    /// pixels with a single sample
+    pub(crate) fn variances(&self) -> impl Iterator<Item = f64> + '_ {
+        self.pixels.iter().map(|pixel| match pixel.stats.count {
+            0 => 0.0,
+            samples => pixel.stats.variance() / samples as f64,
+        })
+    }
+
+    /// Average first hit of every pixel, zero unless the renderer recorded
+    /// them
+    pub(crate) fn first_hits(&self) -> impl Iterator<Item = FirstHit> + '_ {
+        self.pixels
+            .iter()
+            .map(|pixel| pixel.first_hit.average(pixel.stats.count))
+    }
+
+    /// Writes the sums and statistics of every pixel, for checkpoints
+    pub(crate) fn write_pixels(&self, writer: &mut impl Write) -> io::Result<()> {
+        for pixel in &self.pixels {
+            let hit = &pixel.first_hit;
+            let floats = [
+                pixel.sum.x(),
+                pixel.sum.y(),
+                pixel.sum.z(),
+                pixel.stats.mean,
+                pixel.stats.m2,
+                hit.albedo.x(),
+                hit.albedo.y(),
+                hit.albedo.z(),
+                hit.normal.x(),
+                hit.normal.y(),
+                hit.normal.z(),
+                hit.depth,
+                hit.position.x(),
+                hit.position.y(),
+                hit.position.z(),
+            ];
+            for value in floats {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+            let material = hit.material.map_or(0, |kind| kind as u32);
+            for value in [pixel.stats.count, hit.object_id, material] {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads back what `write_pixels` wrote for a framebuffer of this size.
+    /// Memory grows with the pixels read, so a truncated file with a made-up
+    /// size fails before allocating the whole framebuffer.
+    pub(crate) fn read_pixels(
+        width: usize,
+        height: usize,
+        reader: &mut impl Read,
+    ) -> io::Result<Self> {
+        let count = width * height;
+        let mut pixels = Vec::with_capacity(count.min(1 << 16));
+        for _ in 0..count {
+            let mut floats = [0.0; 15];
+            for value in &mut floats {
+                let mut bytes = [0; 8];
+                reader.read_exact(&mut bytes)?;
+                *value = f64::from_le_bytes(bytes);
+            }
+            let mut integers = [0; 3];
+            for value in &mut integers {
+                let mut bytes = [0; 4];
+                reader.read_exact(&mut bytes)?;
+                *value = u32::from_le_bytes(bytes);
+            }
+            let [count, object_id, material] = integers;
+            let material = match material {
+                0 => None,
+                id => Some(MaterialKind::from_id(id).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "unknown material kind")
+                })?),
+            };
+
+            let vector = |offset: usize| Vec3::new([0, 1, 2].map(|c| floats[offset + c]));
+            pixels.push(Pixel {
+                sum: vector(0),
+                stats: PixelStats {
+                    count,
+                    mean: floats[3],
+                    m2: floats[4],
+                },
+                first_hit: FirstHit {
+                    albedo: vector(5),
+                    normal: vector(8),
+                    depth: floats[11],
+                    position: vector(12),
+                    object_id,
+                    material,
+                },
+            });
+        }
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+}
+
+/// Running mean and variance of a pixel's luminance, using Welford's
+/// algorithm so no sample has to be kept
+#[derive(Debug, Clone, Copy, Default)]
+struct PixelStats {
+    count: u32,
+    mean: f64,
+    /// Sum of squared differences from the mean
+    m2: f64,
+}
+
+impl PixelStats {
+    fn add(&mut self, value: f64) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    fn variance(&self) -> f64 {
+        if self.count < 2 {
+            return 0.0;
+        }
+        self.m2 / (self.count - 1) as f64
+    }
+
+    /// Standard error of the mean, relative to the mean. Black pixels have
+    /// no error.
+    fn relative_error(&self) -> f64 {
+        let standard_error = (self.variance() / self.count as f64).sqrt();
+        if standard_error == 0.0 {
+            0.0
+        } else {
+            standard_error / self.mean.abs().max(1e-4)
+        }
+    }
+}
+
+/// Stops sampling pixels whose noise is low enough
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Adaptive {
+    /// Largest standard error, relative to the pixel's mean luminance, a
+    /// converged pixel may have
+    pub(crate) threshold: f64,
+    /// Samples every pixel takes before its noise is trusted
+    pub(crate) min_samples: u32,
+}
+
+pub(crate) struct Renderer<'a> {
+    pub(crate) world: &'a dyn Hittable,
+    /// Shapes diffuse bounces are steered towards, `None` without lights
+    pub(crate) lights: Option<&'a dyn Hittable>,
+    pub(crate) background: &'a dyn Background,
+    pub(crate) camera: &'a Camera,
+    /// Samples of every pixel, or the most a pixel takes with `adaptive`
+    pub(crate) samples_per_pixel: u32,
+    pub(crate) adaptive: Option<Adaptive>,
+    pub(crate) bounces: Bounces,
+    pub(crate) integrator: Integrator,
+    /// MIS weighting of the path tracer
+    pub(crate) heuristic: Heuristic,
+    /// Also record what camera rays hit first, for the denoiser and AOVs
+    pub(crate) first_hits: bool,
+    pub(crate) seed: u64,
+}
+
+impl Renderer<'_> {
+    /// Renders in passes of one sample per pixel until every pixel has
+    /// `samples_per_pixel` samples or has converged. `after_pass` sees the
+    /// image after every pass. Pixels keep the samples they already have, so
+    /// a framebuffer from a checkpoint continues where it stopped.
+    ///
+    /// `bar` counts the samples of all pixels, its length should be the
+    /// number of pixels times `samples_per_pixel`.
+    pub(crate) fn render(
+        &self,
+        framebuffer: &mut Framebuffer,
+        bar: &ProgressBar,
+        mut after_pass: impl FnMut(&Framebuffer),
+    ) {
+        let done: u64 = framebuffer
+            .samples()
+            .map(|samples| u64::from(samples.min(self.samples_per_pixel)))
+            .sum();
+        bar.set_position(done);
+        while self.render_pass(framebuffer, bar) > 0 {
+            after_pass(framebuffer);
+        }
+    }
+
+    /// Takes one more sample of every pixel that needs one, every scanline
+    /// in parallel on the global rayon pool. Returns the number of pixels
+    /// sampled.
+    fn render_pass(&self, framebuffer: &mut Framebuffer, bar: &ProgressBar) -> usize {
+        let width = framebuffer.width;
+        let height = framebuffer.height;
+
+        framebuffer
+            .pixels
+            .par_chunks_mut(width)
+            .enumerate()
+            .map(|(row, scanline)| {
+                let j = height - 1 - row;
+                let mut sampled = 0;
+                for (i, pixel) in scanline.iter_mut().enumerate() {
+                    if self.needs_sample(pixel) {
+                        self.sample_pixel(pixel, i, j, width, height);
+                        sampled += 1;
+                    }
+                }
+                bar.inc(sampled as u64);
+                sampled
+            })
+            .sum()
+    }
+
+    fn needs_sample(&self, pixel: &Pixel) -> bool {
+        let stats = &pixel.stats;
+        if stats.count >= self.samples_per_pixel {
+            return false;
+        }
+        match self.adaptive {
+            Some(adaptive) => {
+                stats.count < adaptive.min_samples || stats.relative_error() > adaptive.threshold
+            }
+            None => true,
+        }
+    }
+
+    /// Adds a sample to pixel `(i, j)`, counted from the bottom left
+    fn sample_pixel(&self, pixel: &mut Pixel, i: usize, j: usize, width: usize, height: usize) {
+        let mut rng = utils::sample_rng(self.seed, i, j, pixel.stats.count);
+        let u = (i as f64 + utils::random_float(&mut rng)) / ((width - 1) as f64);
+        let v = (j as f64 + utils::random_float(&mut rng)) / ((height - 1) as f64);
+        let ray = self.camera.get_ray(u, v, &mut rng);
+        let mut first_hit = FirstHit::zero();
+        let mut record = |hit: Option<&HitRecord>| first_hit = FirstHit::new(hit, self.camera);
+        let on_first_hit = self.first_hits.then_some(&mut record as OnFirstHit<'_>);
+        let radiance = self.radiance(ray, on_first_hit, &mut rng);
+        if self.first_hits {
+            pixel.first_hit = match pixel.stats.count {
+                0 => first_hit,
+                _ => pixel.first_hit + first_hit,
+            };
+        }
+        pixel.sum = pixel.sum + radiance;
+        pixel.stats.add(radiance.luminance());
+    }
+
+    /// One sample of the light arriving along a camera ray, which also passes
+    /// what the ray hits first to `on_first_hit`, if given
+    fn radiance(&self, ray: Ray, on_first_hit: Option<OnFirstHit<'_>>, rng: &mut Rng) -> Color {
+        match self.integrator {
+            Integrator::Mixture => ray.color(
+                self.world,
+                self.lights,
+                self.background,
+                self.bounces,
+                on_first_hit,
+                rng,
+            ),
+            Integrator::Path => integrator::path_trace(
+                ray,
+                self.world,
+                self.lights,
+                self.background,
+                self.bounces,
+                self.heuristic,
+                on_first_hit,
+                rng,
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Adaptive, Framebuffer, PixelStats, Renderer};
+    use crate::{
+        background::Gradient,
+        bvh::BvhNode,
+        camera::{Camera, CameraSettings},
+        color::Color,
+        constant_medium::ConstantMedium,
+        hittable::HittableList,
+        integrator::{Bounces, Heuristic, Integrator},
+        material::{Isotropic, MaterialKind},
+        point::Point,
+        sphere::Sphere,
+        texture::SolidColor,
+        utils,
+        vec3::Vec3,
+    };
+    use indicatif::ProgressBar;
+    use rand::SeedableRng;
+    use std::sync::Arc;
+
+    fn render(seed: u64, samples_per_pixel: u32, adaptive: Option<Adaptive>) -> Framebuffer {
+        let mut world = HittableList::new();
+        utils::random_scene(&mut world, &mut utils::Rng::seed_from_u64(seed));
+        let world = BvhNode::new(world);
+        let settings = CameraSettings {
+            look_from: Point::new([13.0, 2.0, 3.0]),
+            look_at: Point::new([0.0, 0.0, 0.0]),
+            view_up: Vec3::new([0.0, 1.0, 0.0]),
+            vertical_fov: 20.0,
+            aperture: 0.1,
+            dist_to_focus: 10.0,
+            shutter_open: 0.0,
+            shutter_close: 1.0,
+        };
+        let camera = Camera::from_settings(&settings, 1.5);
+        let renderer = Renderer {
+            world: &world,
+            lights: None,
+            background: &Gradient::default(),
+            camera: &camera,
+            samples_per_pixel,
+            adaptive,
+            bounces: Bounces {
+                max: 10,
+                roulette_after: 3,
+            },
+            integrator: Integrator::Mixture,
+            heuristic: Heuristic::Power,
+            first_hits: false,
+            seed,
+        };
+        let mut framebuffer = Framebuffer::new(24, 16);
+        let bar = ProgressBar::hidden();
+        renderer.render(&mut framebuffer, &bar, |_| ());
+        // Every worker counts its samples
+        let samples: u64 = framebuffer.samples().map(u64::from).sum();
+        assert_eq!(bar.position(), samples);
+        framebuffer
+    }
+
+    #[test]
+    fn same_seed_same_image() {
+        let colors = |framebuffer: Framebuffer| framebuffer.colors().collect::<Vec<_>>();
+        assert_eq!(colors(render(7, 2, None)), colors(render(7, 2, None)));
+        assert_ne!(colors(render(7, 2, None)), colors(render(8, 2, None)));
+    }
+
+    #[test]
+    fn pixel_stats() {
+        let values = [0.5, 1.5, 0.25, 2.0, 0.75];
+        let mut stats = PixelStats::default();
+        for value in values {
+            stats.add(value);
+        }
+        let mean = values.iter().sum::<f64>() / 5.0;
+        let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / 4.0;
+        assert!((stats.mean - mean).abs() < 1e-12);
+        assert!((stats.variance() - variance).abs() < 1e-12);
+        assert!((stats.relative_error() - (variance / 5.0).sqrt() / mean).abs() < 1e-12);
+
+        let mut black = PixelStats::default();
+        black.add(0.0);
+        black.add(0.0);
+        assert_eq!(black.relative_error(), 0.0);
+    }
+
+    #[test]
+    fn adaptive_sampling() {
+        let adaptive = Adaptive {
+            threshold: 0.02,
+            min_samples: 4,
+        };
+        let framebuffer = render(7, 64, Some(adaptive));
+        let samples: Vec<u32> = framebuffer.samples().collect();
+        assert!(samples.iter().all(|&n| (4..=64).contains(&n)));
+        // The smooth sky along the top converges right away, the spheres
+        // below need more samples
+        assert!(samples[..framebuffer.width].iter().all(|&n| n == 4));
+        assert!(samples.contains(&64));
+
+        let uniform = render(7, 64, None);
+        assert!(uniform.samples().all(|n| n == 64));
+    }
+
+    #[test]
+    fn first_hits_leave_the_image_alone() {
+        // Hitting smoke draws random numbers, recording first hits must not
+        let mut world = HittableList::new();
+        let albedo = Arc::new(SolidColor::new(Color::new([0.8, 0.8, 0.8])));
+        world.add(Box::new(ConstantMedium::new(
+            Box::new(Sphere::new(
+                Point::new([0.0, 0.0, -2.0]),
+                1.0,
+                Arc::new(Isotropic::with_texture(albedo.clone())),
+            )),
+            0.5,
+            Arc::new(Isotropic::with_texture(albedo)),
+        )));
+        let world = world.numbered();
+        let settings = CameraSettings {
+            look_from: Point::new([0.0, 0.0, 0.0]),
+            look_at: Point::new([0.0, 0.0, -1.0]),
+            view_up: Vec3::new([0.0, 1.0, 0.0]),
+            vertical_fov: 90.0,
+            aperture: 0.0,
+            dist_to_focus: 1.0,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        };
+        let camera = Camera::from_settings(&settings, 1.0);
+        let render = |integrator: Integrator, first_hits: bool| {
+            let renderer = Renderer {
+                world: &world,
+                lights: None,
+                background: &Gradient::default(),
+                camera: &camera,
+                samples_per_pixel: 4,
+                adaptive: None,
+                bounces: Bounces {
+                    max: 10,
+                    roulette_after: 3,
+                },
+                integrator,
+                heuristic: Heuristic::Power,
+                first_hits,
+                seed: 1,
+            };
+            let mut framebuffer = Framebuffer::new(8, 8);
+            renderer.render(&mut framebuffer, &ProgressBar::hidden(), |_| ());
+            framebuffer
+        };
+
+        for integrator in [Integrator::Mixture, Integrator::Path] {
+            let with = render(integrator, true);
+            let without = render(integrator, false);
+            assert!(with.colors().eq(without.colors()));
+            assert!(with
+                .first_hits()
+                .any(|hit| hit.material == Some(MaterialKind::Isotropic)));
+            // Scattering happens inside the smoke, 1 to 3 in front of the camera
+            assert!(with.first_hits().all(|hit| hit.depth <= 3.0));
+            assert!(with.first_hits().any(|hit| hit.depth > 1.0));
+            assert!(without.first_hits().all(|hit| hit.material.is_none()));
+        }
+    }
+}
@@ -0,0 +1,1301 @@
+//! Declarative scene files.
+//!
+//! A scene is a TOML document with a `[camera]` table, named materials under
+//! `[materials.<name>]` and a list of `[[objects]]` referring to those names.
+//!
+//! - Material colors are either `[r, g, b]` or the name of a texture under
+//!   `[textures.<name>]`.
+//! - An optional top-level `background` replaces the sky.
+//! - Objects can be moved with a list of transforms, and a `density` fills
+//!   them with fog or smoke.
+//! - Objects with a `diffuse_light` material are also sampled directly as
+//!   lights, where the shape allows it.
+//!
+//! The fields of every table are described on the `*Desc` type it is read
+//! into. A small scene:
+//!
+//! ```toml
+//! background = [0.0, 0.0, 0.0]
+//!
+//! [camera]
+//! look_from = [13.0, 2.0, 3.0]
+//! look_at = [0.0, 0.0, 0.0]
+//! vertical_fov = 20.0
+//!
+//! [textures.checker]
+//! type = "checker"
+//! odd = [0.2, 0.3, 0.1]
+//! even = [0.9, 0.9, 0.9]
+//! scale = 10.0
+//!
+//! [materials.ground]
+//! type = "lambertian"
+//! albedo = "checker"
+//!
+//! [materials.lamp]
+//! type = "diffuse_light"
+//! emit = [4.0, 4.0, 4.0]
+//!
+//! [[objects]]
+//! type = "sphere"
+//! center = [0.0, -1000.0, 0.0]
+//! radius = 1000.0
+//! material = "ground"
+//!
+//! [[objects]]
+//! type = "sphere"
+//! center = [0.0, 5.0, 0.0]
+//! radius = 1.0
+//! material = "lamp"
+//! ```
+
+use crate::{
+    aarect::{AaRect, Cuboid, Plane},
+    background::{Background, EnvironmentMap, Gradient, SolidColor},
+    camera::CameraSettings,
+    color::Color,
+    constant_medium::ConstantMedium,
+    hittable::{Hittable, HittableList},
+    material::{Dielectric, DiffuseLight, Isotropic, Lambertian, Material, Metal},
+    mesh::Mesh,
+    obj,
+    perlin::Perlin,
+    point::Point,
+    sphere::{MovingSphere, Sphere},
+    texture::{self, CheckerTexture, ImageTexture, NoisePattern, NoiseTexture, Texture, WrapMode},
+    transform::{Transform, Transformed},
+    triangle::{Face, Triangle, VertexBuffers},
+    utils::Rng,
+    vec3::Vec3,
+};
+use rand::SeedableRng;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use toml::Spanned;
+
+pub(crate) struct Scene {
+    pub(crate) world: HittableList,
+    /// The lights among the objects of `world`, shared with it
+    pub(crate) lights: HittableList,
+    pub(crate) camera: CameraSettings,
+    pub(crate) background: Option<Box<dyn Background>>,
+}
+
+#[derive(Debug)]
+pub(crate) enum SceneError {
+    Io(io::Error),
+    Invalid { line: usize, message: String },
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{}", err),
+            Self::Invalid { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl From<io::Error> for SceneError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneFile {
+    background: Option<Spanned<BackgroundDesc>>,
+    camera: Spanned<CameraDesc>,
+    #[serde(default)]
+    textures: HashMap<String, Spanned<TextureDesc>>,
+    #[serde(default)]
+    materials: HashMap<String, Spanned<MaterialDesc>>,
+    #[serde(default)]
+    objects: Vec<Spanned<ObjectEntry>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDesc {
+    look_from: [f64; 3],
+    look_at: [f64; 3],
+    #[serde(default = "default_view_up")]
+    view_up: [f64; 3],
+    vertical_fov: f64,
+    #[serde(default)]
+    aperture: f64,
+    /// Defaults to the distance between `look_from` and `look_at`
+    dist_to_focus: Option<f64>,
+    /// Rays are sent between these times, both 0 by default, so moving
+    /// objects are sharp unless the shutter stays open
+    #[serde(default)]
+    shutter_open: f64,
+    #[serde(default)]
+    shutter_close: f64,
+}
+
+fn default_view_up() -> [f64; 3] {
+    [0.0, 1.0, 0.0]
+}
+
+/// A solid `[r, g, b]` color or a table with a `type`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BackgroundDesc {
+    Solid([f64; 3]),
+    Described(DescribedBackground),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum DescribedBackground {
+    Gradient {
+        bottom: [f64; 3],
+        top: [f64; 3],
+    },
+    /// Equirectangular HDR image
+    Environment {
+        /// Relative to the scene file
+        path: PathBuf,
+        /// Degrees around the vertical axis
+        #[serde(default)]
+        rotation: f64,
+        #[serde(default = "default_intensity")]
+        intensity: f64,
+    },
+}
+
+fn default_intensity() -> f64 {
+    1.0
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum TextureDesc {
+    Solid {
+        color: [f64; 3],
+    },
+    Checker {
+        odd: [f64; 3],
+        even: [f64; 3],
+        #[serde(default = "default_checker_scale")]
+        scale: f64,
+    },
+    /// PNG or JPEG, `path` relative to the scene file
+    Image {
+        path: PathBuf,
+        #[serde(default = "default_wrap")]
+        wrap: WrapDesc,
+    },
+    /// Perlin noise shaped by the `pattern`
+    Noise {
+        pattern: PatternDesc,
+        #[serde(default = "default_noise_scale")]
+        scale: f64,
+        #[serde(default = "default_noise_color")]
+        color: [f64; 3],
+        #[serde(default)]
+        seed: u64,
+    },
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum PatternDesc {
+    Smooth,
+    Turbulence,
+    Marble,
+    Wood,
+}
+
+fn default_noise_scale() -> f64 {
+    1.0
+}
+
+fn default_noise_color() -> [f64; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+fn default_checker_scale() -> f64 {
+    1.0
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum WrapDesc {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+fn default_wrap() -> WrapDesc {
+    WrapDesc::Repeat
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColorDesc {
+    Color([f64; 3]),
+    Texture(String),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDesc {
+    Lambertian {
+        albedo: ColorDesc,
+    },
+    Metal {
+        albedo: ColorDesc,
+        #[serde(default)]
+        fuzz: f64,
+    },
+    Dielectric {
+        index_of_refraction: f64,
+    },
+    DiffuseLight {
+        emit: ColorDesc,
+    },
+    Isotropic {
+        albedo: ColorDesc,
+    },
+}
+
+#[derive(Deserialize)]
+struct ObjectEntry {
+    #[serde(flatten)]
+    shape: ObjectDesc,
+    /// Applied in order, after the shape is built
+    #[serde(default)]
+    transform: Vec<TransformDesc>,
+    /// Turns the (transformed) shape into the boundary of a volume that
+    /// scatters with the shape's material, usually `isotropic`
+    density: Option<f64>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum ObjectDesc {
+    Sphere {
+        center: [f64; 3],
+        radius: f64,
+        material: String,
+    },
+    /// Goes from `center_0` at `time_0` to `center_1` at `time_1`
+    MovingSphere {
+        center_0: [f64; 3],
+        center_1: [f64; 3],
+        #[serde(default)]
+        time_0: f64,
+        #[serde(default = "default_time_1")]
+        time_1: f64,
+        radius: f64,
+        material: String,
+    },
+    Rect {
+        plane: PlaneDesc,
+        /// Corners in the plane's own axes, e.g. `[x, z]` for `xz`
+        min: [f64; 2],
+        max: [f64; 2],
+        /// Position along the remaining axis
+        k: f64,
+        /// Turns the normal around
+        #[serde(default)]
+        flip: bool,
+        material: String,
+    },
+    Box {
+        min: [f64; 3],
+        max: [f64; 3],
+        material: String,
+    },
+    /// `normals` and `uvs` are optional, one for every vertex
+    Triangle {
+        vertices: [[f64; 3]; 3],
+        normals: Option<[[f64; 3]; 3]>,
+        uvs: Option<[[f64; 2]; 3]>,
+        material: String,
+    },
+    /// `normals` and `uvs` are per vertex and share the position indices
+    Mesh {
+        positions: Vec<[f64; 3]>,
+        normals: Option<Vec<[f64; 3]>>,
+        uvs: Option<Vec<[f64; 2]>>,
+        /// Three positions per triangle
+        indices: Vec<[usize; 3]>,
+        material: String,
+    },
+    /// Wavefront OBJ model, `path` relative to the scene file. `material` is
+    /// used for faces without an MTL material
+    Obj {
+        path: PathBuf,
+        material: Option<String>,
+    },
+}
+
+impl ObjectDesc {
+    fn material(&self) -> Option<&String> {
+        match self {
+            Self::Sphere { material, .. }
+            | Self::MovingSphere { material, .. }
+            | Self::Rect { material, .. }
+            | Self::Box { material, .. }
+            | Self::Triangle { material, .. }
+            | Self::Mesh { material, .. } => Some(material),
+            Self::Obj { material, .. } => material.as_ref(),
+        }
+    }
+
+    /// Whether the shape can pick points on itself, so that it can be sampled
+    /// as a light
+    fn can_sample(&self) -> bool {
+        matches!(
+            self,
+            Self::Sphere { .. } | Self::Rect { .. } | Self::Box { .. } | Self::Triangle { .. }
+        )
+    }
+}
+
+fn default_time_1() -> f64 {
+    1.0
+}
+
+/// `{ translate = [x, y, z] }`, `{ rotate = { axis = [x, y, z], degrees = d } }`
+/// or `{ scale = [x, y, z] }`
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum TransformDesc {
+    Translate([f64; 3]),
+    Rotate { axis: [f64; 3], degrees: f64 },
+    Scale([f64; 3]),
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum PlaneDesc {
+    Xy,
+    Xz,
+    Yz,
+}
+
+pub(crate) fn load(path: &Path) -> Result<Scene, SceneError> {
+    let source = fs::read_to_string(path)?;
+    parse(&source, path.parent().unwrap_or_else(|| Path::new(".")))
+}
+
+/// Parses a scene, resolving file references relative to `base_dir`
+pub(crate) fn parse(source: &str, base_dir: &Path) -> Result<Scene, SceneError> {
+    let invalid = |span: Option<Range<usize>>, message: String| SceneError::Invalid {
+        line: span.map_or(1, |span| line_of(source, span.start)),
+        message,
+    };
+
+    let file: SceneFile =
+        toml::from_str(source).map_err(|err| invalid(err.span(), err.message().to_string()))?;
+
+    let camera = camera_settings(file.camera.get_ref());
+    camera
+        .validate()
+        .map_err(|message| invalid(Some(file.camera.span()), message))?;
+
+    let background = match &file.background {
+        Some(desc) => Some(
+            build_background(desc.get_ref(), base_dir)
+                .map_err(|message| invalid(Some(desc.span()), message))?,
+        ),
+        None => None,
+    };
+
+    let mut textures = HashMap::new();
+    for (name, desc) in &file.textures {
+        let texture = build_texture(desc.get_ref(), base_dir).map_err(|message| {
+            invalid(
+                Some(desc.span()),
+                format!("texture '{}': {}", name, message),
+            )
+        })?;
+        textures.insert(name.as_str(), texture);
+    }
+
+    let mut materials = HashMap::new();
+    for (name, desc) in &file.materials {
+        let material = build_material(desc.get_ref(), &textures).map_err(|message| {
+            invalid(
+                Some(desc.span()),
+                format!("material '{}': {}", name, message),
+            )
+        })?;
+        materials.insert(name.as_str(), material);
+    }
+
+    if file.objects.is_empty() {
+        return Err(invalid(None, "the scene has no objects".to_string()));
+    }
+
+    // Lights are sampled directly where the shape allows it, emitting meshes,
+    // moving spheres and volumes are only found by bouncing rays
+    let is_light = |entry: &ObjectEntry| {
+        let emits = entry.shape.material().is_some_and(|name| {
+            file.materials
+                .get(name)
+                .is_some_and(|desc| matches!(desc.get_ref(), MaterialDesc::DiffuseLight { .. }))
+        });
+        emits && entry.shape.can_sample()
+    };
+
+    // Models used by several objects are loaded once and shared
+    let mut models: HashMap<(&Path, Option<&str>), Arc<dyn Hittable>> = HashMap::new();
+    let mut world = HittableList::new();
+    let mut lights = HittableList::new();
+    for desc in &file.objects {
+        let material_of = |name: &String| {
+            materials
+                .get(name.as_str())
+                .map(Arc::clone)
+                .ok_or_else(|| invalid(Some(desc.span()), format!("unknown material '{}'", name)))
+        };
+        let object: Box<dyn Hittable> = match &desc.get_ref().shape {
+            ObjectDesc::Sphere {
+                center,
+                radius,
+                material,
+            } => {
+                let material = material_of(material)?;
+                if !(radius.is_finite() && *radius != 0.0 && all_finite(center)) {
+                    return Err(invalid(
+                        Some(desc.span()),
+                        "a sphere needs a finite center and a non-zero radius".to_string(),
+                    ));
+                }
+                Box::new(Sphere::new(Point::new(*center), *radius, material))
+            }
+            ObjectDesc::MovingSphere {
+                center_0,
+                center_1,
+                time_0,
+                time_1,
+                radius,
+                material,
+            } => {
+                let material = material_of(material)?;
+                let finite = all_finite(center_0)
+                    && all_finite(center_1)
+                    && time_0.is_finite()
+                    && time_1.is_finite();
+                if !(finite && radius.is_finite() && *radius != 0.0 && time_0 <= time_1) {
+                    return Err(invalid(
+                        Some(desc.span()),
+                        "a moving sphere needs finite centers, a non-zero radius and time_0 \
+                         before time_1"
+                            .to_string(),
+                    ));
+                }
+                Box::new(MovingSphere::new(
+                    (Point::new(*center_0), Point::new(*center_1)),
+                    (*time_0, *time_1),
+                    *radius,
+                    material,
+                ))
+            }
+            ObjectDesc::Rect {
+                plane,
+                min,
+                max,
+                k,
+                flip,
+                material,
+            } => {
+                let material = material_of(material)?;
+                let finite = min.iter().chain(max).chain([k]).all(|c| c.is_finite());
+                if !(finite && min[0] != max[0] && min[1] != max[1]) {
+                    return Err(invalid(
+                        Some(desc.span()),
+                        "a rect needs finite corners with a non-zero area".to_string(),
+                    ));
+                }
+                let plane = match plane {
+                    PlaneDesc::Xy => Plane::Xy,
+                    PlaneDesc::Xz => Plane::Xz,
+                    PlaneDesc::Yz => Plane::Yz,
+                };
+                let rect = AaRect::new(plane, (min[0], max[0]), (min[1], max[1]), *k, material);
+                if *flip {
+                    Box::new(rect.flipped())
+                } else {
+                    Box::new(rect)
+                }
+            }
+            ObjectDesc::Box { min, max, material } => {
+                let material = material_of(material)?;
+                let non_empty = min.iter().zip(max).all(|(a, b)| a != b);
+                if !(all_finite(min) && all_finite(max) && non_empty) {
+                    return Err(invalid(
+                        Some(desc.span()),
+                        "a box needs finite corners with a non-zero volume".to_string(),
+                    ));
+                }
+                Box::new(Cuboid::new(Point::new(*min), Point::new(*max), material))
+            }
+            ObjectDesc::Triangle {
+                vertices,
+                normals,
+                uvs,
+                material,
+            } => {
+                let material = material_of(material)?;
+                let (vertices, face) = vertex_buffers(
+                    vertices,
+                    normals.as_ref().map(|n| &n[..]),
+                    uvs.as_ref().map(|uvs| &uvs[..]),
+                    &[[0, 1, 2]],
+                )
+                .map_err(|message| invalid(Some(desc.span()), message))?;
+                Box::new(Triangle::new(Arc::new(vertices), face[0], material))
+            }
+            ObjectDesc::Mesh {
+                positions,
+                normals,
+                uvs,
+                indices,
+                material,
+            } => {
+                let material = material_of(material)?;
+                let (vertices, faces) =
+                    vertex_buffers(positions, normals.as_deref(), uvs.as_deref(), indices)
+                        .map_err(|message| invalid(Some(desc.span()), message))?;
+                Box::new(Mesh::new(Arc::new(vertices), &faces, material))
+            }
+            ObjectDesc::Obj { path, material } => {
+                let key = (path.as_path(), material.as_deref());
+                let model = match models.get(&key) {
+                    Some(model) => Arc::clone(model),
+                    None => {
+                        let material = match material {
+                            Some(material) => material_of(material)?,
+                            None => Arc::new(Lambertian::new(Color::new([0.5, 0.5, 0.5]))),
+                        };
+                        let model: Arc<dyn Hittable> = Arc::new(
+                            obj::load(&base_dir.join(path), material)
+                                .map_err(|err| invalid(Some(desc.span()), err.to_string()))?,
+                        );
+                        models.insert(key, Arc::clone(&model));
+                        model
+                    }
+                };
+                Box::new(model)
+            }
+        };
+
+        let transforms = &desc.get_ref().transform;
+        let object: Box<dyn Hittable> = if transforms.is_empty() {
+            object
+        } else {
+            let transform =
+                transform_of(transforms).map_err(|message| invalid(Some(desc.span()), message))?;
+            Box::new(Transformed::new(Arc::from(object), transform))
+        };
+
+        match desc.get_ref().density {
+            Some(density) if density > 0.0 && density.is_finite() => {
+                let material = desc.get_ref().shape.material().ok_or_else(|| {
+                    invalid(Some(desc.span()), "a volume needs a material".to_string())
+                })?;
+                let material = material_of(material)?;
+                world.add(Box::new(ConstantMedium::new(object, density, material)));
+            }
+            Some(density) => {
+                return Err(invalid(
+                    Some(desc.span()),
+                    format!("density must be positive, got {}", density),
+                ))
+            }
+            None if is_light(desc.get_ref()) => {
+                let object: Arc<dyn Hittable> = Arc::from(object);
+                lights.add(Box::new(Arc::clone(&object)));
+                world.add(Box::new(object));
+            }
+            None => world.add(object),
+        }
+    }
+
+    Ok(Scene {
+        world,
+        lights,
+        camera,
+        background,
+    })
+}
+
+fn transform_of(descs: &[TransformDesc]) -> Result<Transform, String> {
+    descs
+        .iter()
+        .try_fold(Transform::identity(), |transform, desc| {
+            let next = match desc {
+                TransformDesc::Translate(offset) if all_finite(offset) => {
+                    Transform::translation(Vec3::new(*offset))
+                }
+                TransformDesc::Rotate { axis, degrees }
+                    if all_finite(axis) && degrees.is_finite() && !Vec3::new(*axis).near_zero() =>
+                {
+                    Transform::rotation(Vec3::new(*axis), *degrees)
+                }
+                TransformDesc::Scale(factors)
+                    if all_finite(factors) && factors.iter().all(|f| *f != 0.0) =>
+                {
+                    Transform::scaling(Vec3::new(*factors))
+                }
+                TransformDesc::Translate(_) => return Err("translation must be finite".to_string()),
+                TransformDesc::Rotate { .. } => {
+                    return Err("rotation needs a non-zero axis and a finite angle".to_string())
+                }
+                TransformDesc::Scale(_) => {
+                    return Err("scale factors must be finite and non-zero".to_string())
+                }
+            };
+            Ok(transform.then(&next))
+        })
+}
+
+/// Checks inline vertex data, where normals and UVs (if any) are given per
+/// position and share its indices
+fn vertex_buffers(
+    positions: &[[f64; 3]],
+    normals: Option<&[[f64; 3]]>,
+    uvs: Option<&[[f64; 2]]>,
+    indices: &[[usize; 3]],
+) -> Result<(VertexBuffers, Vec<Face>), String> {
+    if indices.is_empty() {
+        return Err("a mesh needs at least one triangle".to_string());
+    }
+    if !positions
+        .iter()
+        .chain(normals.unwrap_or_default())
+        .all(all_finite)
+        || !uvs
+            .unwrap_or_default()
+            .iter()
+            .flatten()
+            .all(|c| c.is_finite())
+    {
+        return Err("vertex data must be finite".to_string());
+    }
+    for (name, len) in [
+        ("normals", normals.map(<[_]>::len)),
+        ("uvs", uvs.map(<[_]>::len)),
+    ] {
+        if len.is_some_and(|len| len != positions.len()) {
+            return Err(format!("expected one entry in {} per position", name));
+        }
+    }
+
+    let vertices = VertexBuffers {
+        positions: positions.iter().map(|p| Point::new(*p)).collect(),
+        normals: normals
+            .unwrap_or_default()
+            .iter()
+            .map(|n| Vec3::new(*n))
+            .collect(),
+        uvs: uvs
+            .unwrap_or_default()
+            .iter()
+            .map(|[u, v]| (*u, *v))
+            .collect(),
+    };
+    let faces: Vec<Face> = indices
+        .iter()
+        .map(|indices| Face {
+            normals: normals.map(|_| *indices),
+            uvs: uvs.map(|_| *indices),
+            ..Face::new(*indices)
+        })
+        .collect();
+    if let Some(face) = faces.iter().find(|face| !vertices.contains(face)) {
+        return Err(format!(
+            "vertex index out of bounds in {:?}, there are {} positions",
+            face.positions,
+            positions.len()
+        ));
+    }
+    if vertices.normals.iter().any(|n| n.near_zero()) {
+        return Err("normals must not be zero".to_string());
+    }
+    Ok((vertices, faces))
+}
+
+fn camera_settings(desc: &CameraDesc) -> CameraSettings {
+    let look_from = Point::new(desc.look_from);
+    let look_at = Point::new(desc.look_at);
+    CameraSettings {
+        look_from,
+        look_at,
+        view_up: Vec3::new(desc.view_up),
+        vertical_fov: desc.vertical_fov,
+        aperture: desc.aperture,
+        dist_to_focus: desc
+            .dist_to_focus
+            .unwrap_or_else(|| (look_from - look_at).len()),
+        shutter_open: desc.shutter_open,
+        shutter_close: desc.shutter_close,
+    }
+}
+
+fn build_background(desc: &BackgroundDesc, base_dir: &Path) -> Result<Box<dyn Background>, String> {
+    match desc {
+        BackgroundDesc::Solid(solid) => Ok(Box::new(SolidColor::new(color(solid)?))),
+        BackgroundDesc::Described(DescribedBackground::Gradient { bottom, top }) => {
+            Ok(Box::new(Gradient::new(color(bottom)?, color(top)?)))
+        }
+        BackgroundDesc::Described(DescribedBackground::Environment {
+            path,
+            rotation,
+            intensity,
+        }) => {
+            if !rotation.is_finite() {
+                return Err("rotation must be finite".to_string());
+            }
+            if !(*intensity >= 0.0 && intensity.is_finite()) {
+                return Err(format!("intensity must not be negative, got {}", intensity));
+            }
+            let map = EnvironmentMap::load(&base_dir.join(path), *rotation, *intensity)
+                .map_err(|err| err.to_string())?;
+            Ok(Box::new(map))
+        }
+    }
+}
+
+fn build_texture(desc: &TextureDesc, base_dir: &Path) -> Result<Arc<dyn Texture>, String> {
+    match desc {
+        TextureDesc::Solid { color: solid } => {
+            Ok(Arc::new(texture::SolidColor::new(color(solid)?)))
+        }
+        TextureDesc::Checker { odd, even, scale } => {
+            if !(*scale > 0.0 && scale.is_finite()) {
+                return Err(format!("scale must be positive, got {}", scale));
+            }
+            Ok(Arc::new(CheckerTexture::from_colors(
+                color(odd)?,
+                color(even)?,
+                *scale,
+            )))
+        }
+        TextureDesc::Image { path, wrap } => {
+            let wrap = match wrap {
+                WrapDesc::Repeat => WrapMode::Repeat,
+                WrapDesc::Clamp => WrapMode::Clamp,
+                WrapDesc::Mirror => WrapMode::Mirror,
+            };
+            let image =
+                ImageTexture::load(&base_dir.join(path), wrap).map_err(|err| err.to_string())?;
+            Ok(Arc::new(image))
+        }
+        TextureDesc::Noise {
+            pattern,
+            scale,
+            color: tint,
+            seed,
+        } => {
+            if !(*scale > 0.0 && scale.is_finite()) {
+                return Err(format!("scale must be positive, got {}", scale));
+            }
+            let pattern = match pattern {
+                PatternDesc::Smooth => NoisePattern::Smooth,
+                PatternDesc::Turbulence => NoisePattern::Turbulence,
+                PatternDesc::Marble => NoisePattern::Marble,
+                PatternDesc::Wood => NoisePattern::Wood,
+            };
+            let noise = Perlin::new(&mut Rng::seed_from_u64(*seed));
+            Ok(Arc::new(NoiseTexture::new(
+                noise,
+                pattern,
+                *scale,
+                color(tint)?,
+            )))
+        }
+    }
+}
+
+type Textures<'a> = HashMap<&'a str, Arc<dyn Texture>>;
+
+fn build_material(
+    desc: &MaterialDesc,
+    textures: &Textures<'_>,
+) -> Result<Arc<dyn Material>, String> {
+    match desc {
+        MaterialDesc::Lambertian { albedo } => Ok(Arc::new(Lambertian::with_texture(texture_of(
+            albedo, textures,
+        )?))),
+        MaterialDesc::Metal { albedo, fuzz } => {
+            if !(0.0..=1.0).contains(fuzz) {
+                return Err(format!("fuzz must be between 0 and 1, got {}", fuzz));
+            }
+            Ok(Arc::new(Metal::with_texture(
+                texture_of(albedo, textures)?,
+                *fuzz,
+            )))
+        }
+        MaterialDesc::Dielectric {
+            index_of_refraction,
+        } => {
+            if !(*index_of_refraction > 0.0 && index_of_refraction.is_finite()) {
+                return Err(format!(
+                    "index_of_refraction must be positive, got {}",
+                    index_of_refraction
+                ));
+            }
+            Ok(Arc::new(Dielectric::new(*index_of_refraction)))
+        }
+        MaterialDesc::DiffuseLight { emit } => Ok(Arc::new(DiffuseLight::with_texture(
+            texture_of(emit, textures)?,
+        ))),
+        MaterialDesc::Isotropic { albedo } => Ok(Arc::new(Isotropic::with_texture(texture_of(
+            albedo, textures,
+        )?))),
+    }
+}
+
+fn texture_of(desc: &ColorDesc, textures: &Textures<'_>) -> Result<Arc<dyn Texture>, String> {
+    match desc {
+        ColorDesc::Color(solid) => Ok(Arc::new(texture::SolidColor::new(color(solid)?))),
+        ColorDesc::Texture(name) => textures
+            .get(name.as_str())
+            .cloned()
+            .ok_or_else(|| format!("unknown texture '{}'", name)),
+    }
+}
+
+fn color(components: &[f64; 3]) -> Result<Color, String> {
+    if components.iter().all(|c| *c >= 0.0 && c.is_finite()) {
+        Ok(Color::new(*components))
+    } else {
+        Err(format!(
+            "color components must not be negative, got {:?}",
+            components
+        ))
+    }
+}
+
+fn all_finite(components: &[f64; 3]) -> bool {
+    components.iter().all(|c| c.is_finite())
+}
+
+fn line_of(source: &str, offset: usize) -> usize {
+    source[..offset.min(source.len())].matches('\n').count() + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SceneError;
+    use crate::{color::Color, hittable::Hittable, point::Point, ray::Ray, utils::Rng, vec3::Vec3};
+    use rand::SeedableRng;
+    use std::path::Path;
+
+    fn parse(source: &str) -> Result<super::Scene, SceneError> {
+        super::parse(source, Path::new("."))
+    }
+
+    const CAMERA: &str = "
+[camera]
+look_from = [13.0, 2.0, 3.0]
+look_at = [0.0, 0.0, 0.0]
+vertical_fov = 20.0
+";
+
+    fn error_line(source: &str) -> usize {
+        match parse(source) {
+            Err(SceneError::Invalid { line, .. }) => line,
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("scene should not parse"),
+        }
+    }
+
+    #[test]
+    fn shared_materials() {
+        let source = format!(
+            "{}
+[materials.glass]
+type = \"dielectric\"
+index_of_refraction = 1.5
+
+[[objects]]
+type = \"sphere\"
+center = [0.0, 1.0, 0.0]
+radius = 1.0
+material = \"glass\"
+
+[[objects]]
+type = \"sphere\"
+center = [4.0, 1.0, 0.0]
+radius = 1.0
+material = \"glass\"
+",
+            CAMERA
+        );
+        let scene = parse(&source).unwrap();
+        assert_eq!(scene.world.into_objects().len(), 2);
+        assert_eq!(
+            scene.camera.dist_to_focus,
+            (13.0f64 * 13.0 + 4.0 + 9.0).sqrt()
+        );
+    }
+
+    #[test]
+    fn background_and_lights() {
+        let source = format!(
+            "background = [0.0, 0.0, 0.0]
+{}
+[materials.lamp]
+type = \"diffuse_light\"
+emit = [4.0, 4.0, 4.0]
+
+[[objects]]
+type = \"sphere\"
+center = [0.0, 5.0, 0.0]
+radius = 1.0
+material = \"lamp\"
+",
+            CAMERA
+        );
+        let scene = parse(&source).unwrap();
+        let ray = Ray::new(Point::new([0.0, 0.0, 0.0]), Vec3::new([0.0, 1.0, 0.0]), 0.0);
+        let background = scene.background.unwrap().color(&ray);
+        assert_eq!(background, Color::new([0.0, 0.0, 0.0]));
+
+        // The lamp is sampled as a light
+        let mut rng = Rng::seed_from_u64(0);
+        let light = scene.lights.hit(&ray, 0.001, f64::INFINITY, &mut rng);
+        assert_eq!(light.map(|light| light.t), Some(4.0));
+    }
+
+    #[test]
+    fn emitting_meshes_are_not_lights() {
+        let source = format!(
+            "{}
+[materials.lamp]
+type = \"diffuse_light\"
+emit = [4.0, 4.0, 4.0]
+
+[[objects]]
+type = \"mesh\"
+positions = [[-1.0, 5.0, -1.0], [1.0, 5.0, -1.0], [1.0, 5.0, 1.0], [-1.0, 5.0, 1.0]]
+indices = [[0, 1, 2], [0, 2, 3]]
+material = \"lamp\"
+",
+            CAMERA
+        );
+        let scene = parse(&source).unwrap();
+        assert!(scene.lights.is_empty());
+
+        let mut rng = Rng::seed_from_u64(0);
+        let ray = Ray::new(Point::new([0.0, 0.0, 0.0]), Vec3::new([0.0, 1.0, 0.0]), 0.0);
+        let hit = scene.world.hit(&ray, 0.001, f64::INFINITY, &mut rng);
+        assert_eq!(hit.map(|hit| hit.t), Some(5.0));
+    }
+
+    #[test]
+    fn missing_environment_map() {
+        let source = format!(
+            "{}
+[background]
+type = \"environment\"
+path = \"does/not/exist.hdr\"
+
+[materials.white]
+type = \"lambertian\"
+albedo = [0.73, 0.73, 0.73]
+
+[[objects]]
+type = \"sphere\"
+center = [0.0, 5.0, 0.0]
+radius = 1.0
+material = \"white\"
+",
+            CAMERA
+        );
+        assert_eq!(error_line(&source), 7);
+
+        // The map is the only problem
+        let background = source.find("[background]").unwrap();
+        let materials = source.find("[materials.white]").unwrap();
+        let without_map = source.replace(&source[background..materials], "");
+        assert!(parse(&without_map).is_ok());
+    }
+
+    #[test]
+    fn textured_materials() {
+        let source = format!(
+            "{}
+[textures.checker]
+type = \"checker\"
+odd = [0.0, 0.0, 0.0]
+even = [1.0, 1.0, 1.0]
+scale = 4.0
+
+[materials.ground]
+type = \"lambertian\"
+albedo = \"checker\"
+
+[materials.unused]
+type = \"metal\"
+albedo = \"nope\"
+
+[[objects]]
+type = \"sphere\"
+center = [0.0, -1000.0, 0.0]
+radius = 1000.0
+material = \"ground\"
+",
+            CAMERA
+        );
+        assert_eq!(error_line(&source), 17);
+        let source = source.replace("\"nope\"", "[0.5, 0.5, 0.5]");
+        assert!(parse(&source).is_ok());
+    }
+
+    #[test]
+    fn rects_and_boxes() {
+        let mut rng = Rng::seed_from_u64(0);
+        let source = format!(
+            "{}
+[materials.white]
+type = \"lambertian\"
+albedo = [0.73, 0.73, 0.73]
+
+[[objects]]
+type = \"rect\"
+plane = \"xz\"
+min = [-1.0, -1.0]
+max = [1.0, 1.0]
+k = 3.0
+flip = true
+material = \"white\"
+
+[[objects]]
+type = \"box\"
+min = [-1.0, 0.0, -1.0]
+max = [1.0, 1.0, 1.0]
+material = \"white\"
+",
+            CAMERA
+        );
+        let scene = parse(&source).unwrap();
+        let ray = Ray::new(Point::new([0.0, 2.0, 0.0]), Vec3::new([0.0, 1.0, 0.0]), 0.0);
+        let hit = scene
+            .world
+            .hit(&ray, 0.001, f64::INFINITY, &mut rng)
+            .unwrap();
+        assert!(hit.front_face);
+        assert_eq!(hit.normal, Vec3::new([0.0, -1.0, 0.0]));
+
+        let flat = source.replace("max = [1.0, 1.0, 1.0]", "max = [1.0, 0.0, 1.0]");
+        assert_eq!(error_line(&flat), 20);
+    }
+
+    #[test]
+    fn triangles_and_meshes() {
+        let mut rng = Rng::seed_from_u64(0);
+        let source = format!(
+            "{}
+[materials.white]
+type = \"lambertian\"
+albedo = [0.73, 0.73, 0.73]
+
+[[objects]]
+type = \"triangle\"
+vertices = [[0.0, 0.0, 0.0], [0.0, 0.0, 1.0], [1.0, 0.0, 0.0]]
+uvs = [[0.0, 0.0], [0.0, 1.0], [1.0, 0.0]]
+material = \"white\"
+
+[[objects]]
+type = \"mesh\"
+positions = [[0.0, 2.0, 0.0], [1.0, 2.0, 0.0], [1.0, 3.0, 0.0], [0.0, 3.0, 0.0]]
+indices = [[0, 1, 2], [0, 2, 3]]
+material = \"white\"
+",
+            CAMERA
+        );
+        let scene = parse(&source).unwrap();
+        let ray = Ray::new(
+            Point::new([0.25, 1.0, 0.5]),
+            Vec3::new([0.0, -1.0, 0.0]),
+            0.0,
+        );
+        let hit = scene
+            .world
+            .hit(&ray, 0.001, f64::INFINITY, &mut rng)
+            .unwrap();
+        assert_eq!((hit.u, hit.v), (0.25, 0.5));
+
+        let out_of_bounds = source.replace("[0, 2, 3]", "[0, 2, 4]");
+        assert_eq!(error_line(&out_of_bounds), 17);
+        let missing_uv = source.replace("[1.0, 0.0]]", "]");
+        assert_eq!(error_line(&missing_uv), 11);
+    }
+
+    #[test]
+    fn transformed_objects() {
+        let mut rng = Rng::seed_from_u64(0);
+        let source = format!(
+            "{}
+[materials.white]
+type = \"lambertian\"
+albedo = [0.73, 0.73, 0.73]
+
+[[objects]]
+type = \"box\"
+min = [-1.0, -1.0, -1.0]
+max = [1.0, 1.0, 1.0]
+material = \"white\"
+transform = [
+    {{ scale = [1.0, 2.0, 1.0] }},
+    {{ rotate = {{ axis = [0.0, 0.0, 1.0], degrees = 90.0 }} }},
+    {{ translate = [0.0, 5.0, 0.0] }},
+]
+",
+            CAMERA
+        );
+        let scene = parse(&source).unwrap();
+        // The box is now 4 wide along x and 2 high, centered at y = 5
+        let ray = Ray::new(Point::new([1.9, 0.0, 0.0]), Vec3::new([0.0, 1.0, 0.0]), 0.0);
+        let hit = scene
+            .world
+            .hit(&ray, 0.001, f64::INFINITY, &mut rng)
+            .unwrap();
+        assert!((hit.t - 4.0).abs() < 1e-9);
+        assert!((hit.normal - Vec3::new([0.0, -1.0, 0.0])).len() < 1e-9);
+
+        let flat = source.replace("[1.0, 2.0, 1.0]", "[1.0, 0.0, 1.0]");
+        assert_eq!(error_line(&flat), 11);
+        assert!(parse(&source.replace("max =", "maximum =")).is_err());
+    }
+
+    #[test]
+    fn motion_blur() {
+        let mut rng = Rng::seed_from_u64(0);
+        let source = format!(
+            "{}shutter_close = 1.0
+
+[materials.white]
+type = \"lambertian\"
+albedo = [0.73, 0.73, 0.73]
+
+[[objects]]
+type = \"moving_sphere\"
+center_0 = [0.0, 0.0, 0.0]
+center_1 = [0.0, 2.0, 0.0]
+radius = 0.5
+material = \"white\"
+",
+            CAMERA
+        );
+        let scene = parse(&source).unwrap();
+        assert_eq!(
+            (scene.camera.shutter_open, scene.camera.shutter_close),
+            (0.0, 1.0)
+        );
+        let ray = |time| {
+            Ray::new(
+                Point::new([0.0, 2.0, 5.0]),
+                Vec3::new([0.0, 0.0, -1.0]),
+                time,
+            )
+        };
+        assert!(scene
+            .world
+            .hit(&ray(0.0), 0.001, f64::INFINITY, &mut rng)
+            .is_none());
+        assert!(scene
+            .world
+            .hit(&ray(1.0), 0.001, f64::INFINITY, &mut rng)
+            .is_some());
+
+        let backwards = source.replace("shutter_close = 1.0", "shutter_open = 1.0");
+        assert_eq!(error_line(&backwards), 2);
+    }
+
+    #[test]
+    fn volumes() {
+        let mut rng = Rng::seed_from_u64(0);
+        let source = format!(
+            "{}
+[materials.smoke]
+type = \"isotropic\"
+albedo = [0.0, 0.0, 0.0]
+
+[[objects]]
+type = \"box\"
+min = [-1.0, -1.0, -1.0]
+max = [1.0, 1.0, 1.0]
+material = \"smoke\"
+density = 1000000.0
+transform = [{{ translate = [0.0, 0.0, -5.0] }}]
+",
+            CAMERA
+        );
+        let scene = parse(&source).unwrap();
+        let ray = Ray::new(
+            Point::new([0.0, 0.0, 0.0]),
+            Vec3::new([0.0, 0.0, -1.0]),
+            0.0,
+        );
+        let hit = scene
+            .world
+            .hit(&ray, 0.001, f64::INFINITY, &mut rng)
+            .unwrap();
+        assert!((4.0..4.01).contains(&hit.t));
+
+        let empty = source.replace("density = 1000000.0", "density = 0.0");
+        assert_eq!(error_line(&empty), 11);
+    }
+
+    #[test]
+    fn unknown_material_reports_line() {
+        let source = format!(
+            "{}
+[[objects]]
+type = \"sphere\"
+center = [0.0, 1.0, 0.0]
+radius = 1.0
+material = \"missing\"
+",
+            CAMERA
+        );
+        assert_eq!(error_line(&source), 7);
+    }
+
+    #[test]
+    fn malformed_material_reports_line() {
+        let source = format!(
+            "{}
+[materials.shiny]
+type = \"metal\"
+albedo = [0.5, 0.5]
+",
+            CAMERA
+        );
+        assert_eq!(error_line(&source), 7);
+    }
+
+    #[test]
+    fn invalid_camera() {
+        let source = "
+[camera]
+look_from = [1.0, 2.0, 3.0]
+look_at = [1.0, 2.0, 3.0]
+vertical_fov = 20.0
+
+[[objects]]
+type = \"sphere\"
+center = [0.0, 1.0, 0.0]
+radius = 1.0
+material = \"missing\"
+";
+        assert_eq!(error_line(source), 2);
+    }
+}
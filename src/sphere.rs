@@ -1,64 +1,253 @@
 use crate::{
+    aabb::Aabb,
     hittable::{HitRecord, Hittable},
     material::Material,
+    onb::Onb,
     point::Point,
     ray::Ray,
+    utils::{self, Rng},
+    vec3::Vec3,
 };
+use std::{f64::consts::PI, sync::Arc};
 
-pub(crate) struct Sphere<M>
-where
-    M: Material + Copy + 'static,
-{
+pub(crate) struct Sphere {
     center: Point,
     radius: f64,
-    material: Box<M>,
+    material: Arc<dyn Material>,
 }
 
-impl<M> Sphere<M>
-where
-    M: Material + Copy + 'static,
-{
-    pub(crate) fn new(center: Point, radius: f64, material: Box<M>) -> Self {
+impl Sphere {
+    pub(crate) fn new(center: Point, radius: f64, material: Arc<dyn Material>) -> Self {
         Self {
             center,
             radius,
             material,
         }
     }
+
+    /// Spherical coordinates of a point on the unit sphere. `u` goes around
+    /// the y axis starting at -x, `v` goes from the bottom pole to the top.
+    fn uv(point: &Point) -> (f64, f64) {
+        let theta = (-point.y()).clamp(-1.0, 1.0).acos();
+        let phi = (-point.z()).atan2(point.x()) + PI;
+        (phi / (2.0 * PI), theta / PI)
+    }
 }
 
-impl<M> Hittable for Sphere<M>
-where
-    M: Material + Copy + 'static,
-{
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let oc = ray.origin - self.center;
-        let a = ray.direction.len_square();
-        let half_b = oc.dot(&ray.direction);
-        let c = oc.len_square() - self.radius * self.radius;
-
-        let discriminant = half_b * half_b - a * c;
-        if discriminant < 0.0 {
-            return None;
+impl Hittable for Sphere {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _rng: &mut Rng) -> Option<HitRecord<'_>> {
+        hit_sphere(
+            &self.center,
+            self.radius,
+            self.material.as_ref(),
+            ray,
+            t_min,
+            t_max,
+        )
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(sphere_box(&self.center, self.radius))
+    }
+
+    /// Uniform over the cone of directions the sphere covers, or over all
+    /// directions from inside
+    fn pdf_value(&self, origin: &Point, direction: &Vec3, rng: &mut Rng) -> f64 {
+        let distance_squared = (self.center - *origin).len_square();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            return 1.0 / (4.0 * PI);
         }
-        let discriminant_sqrt = discriminant.sqrt();
+        let ray = Ray::new(*origin, *direction, 0.0);
+        if self.hit(&ray, 0.001, f64::INFINITY, rng).is_none() {
+            return 0.0;
+        }
+
+        let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+        let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
+        1.0 / solid_angle
+    }
 
-        // Find the nearest root that lies in the acceptable range
-        let mut root = (-half_b - discriminant_sqrt) / a;
+    fn random(&self, origin: &Point, rng: &mut Rng) -> Vec3 {
+        let direction = self.center - *origin;
+        let distance_squared = direction.len_square();
+        if distance_squared <= self.radius * self.radius {
+            return Vec3::random_unit_vec(rng);
+        }
+        let uvw = Onb::from_w(&direction);
+        uvw.local(&random_to_sphere(self.radius, distance_squared, rng))
+    }
+}
+
+/// Direction around the z axis into a sphere of `radius` whose center is
+/// `distance_squared` away on the z axis
+fn random_to_sphere(radius: f64, distance_squared: f64, rng: &mut Rng) -> Vec3 {
+    let r_1 = utils::random_float(rng);
+    let r_2 = utils::random_float(rng);
+    let z = 1.0 + r_2 * ((1.0 - radius * radius / distance_squared).sqrt() - 1.0);
+
+    let phi = 2.0 * PI * r_1;
+    let (sin_phi, cos_phi) = phi.sin_cos();
+    let sin_theta = (1.0 - z * z).sqrt();
+    Vec3::new([cos_phi * sin_theta, sin_phi * sin_theta, z])
+}
+
+/// Sphere moving in a straight line from `centers.0` at `times.0` to
+/// `centers.1` at `times.1`. It stays put before and after.
+pub(crate) struct MovingSphere {
+    centers: (Point, Point),
+    times: (f64, f64),
+    radius: f64,
+    material: Arc<dyn Material>,
+}
+
+impl MovingSphere {
+    pub(crate) fn new(
+        centers: (Point, Point),
+        times: (f64, f64),
+        radius: f64,
+        material: Arc<dyn Material>,
+    ) -> Self {
+        Self {
+            centers,
+            times,
+            radius,
+            material,
+        }
+    }
+
+    fn center(&self, time: f64) -> Point {
+        let (time_0, time_1) = self.times;
+        if time_1 <= time_0 {
+            return self.centers.0;
+        }
+        let progress = ((time - time_0) / (time_1 - time_0)).clamp(0.0, 1.0);
+        self.centers.0 + (self.centers.1 - self.centers.0) * progress
+    }
+}
+
+impl Hittable for MovingSphere {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _rng: &mut Rng) -> Option<HitRecord<'_>> {
+        hit_sphere(
+            &self.center(ray.time),
+            self.radius,
+            self.material.as_ref(),
+            ray,
+            t_min,
+            t_max,
+        )
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::surrounding(
+            &sphere_box(&self.centers.0, self.radius),
+            &sphere_box(&self.centers.1, self.radius),
+        ))
+    }
+}
+
+fn hit_sphere<'a>(
+    center: &Point,
+    radius: f64,
+    material: &'a dyn Material,
+    ray: &Ray,
+    t_min: f64,
+    t_max: f64,
+) -> Option<HitRecord<'a>> {
+    let oc = ray.origin - *center;
+    let a = ray.direction.len_square();
+    let half_b = oc.dot(&ray.direction);
+    let c = oc.len_square() - radius * radius;
+
+    let discriminant = half_b * half_b - a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let discriminant_sqrt = discriminant.sqrt();
+
+    // Find the nearest root that lies in the acceptable range
+    let mut root = (-half_b - discriminant_sqrt) / a;
+    if root < t_min || t_max < root {
+        root = (-half_b + discriminant_sqrt) / a;
         if root < t_min || t_max < root {
-            root = (-half_b + discriminant_sqrt) / a;
-            if root < t_min || t_max < root {
-                return None;
-            }
+            return None;
         }
+    }
+
+    let point = ray.at(root);
+    let outward_normal = (point - *center) / radius;
+    let mut hit_record = HitRecord::new(
+        point,
+        outward_normal,
+        root,
+        Sphere::uv(&outward_normal),
+        false,
+        material,
+    );
+    hit_record.set_face_normal(ray, outward_normal);
+
+    Some(hit_record)
+}
+
+fn sphere_box(center: &Point, radius: f64) -> Aabb {
+    let radius = radius.abs();
+    let extent = Point::new([radius, radius, radius]);
+    Aabb::new(*center - extent, *center + extent)
+}
 
-        let point = ray.at(root);
-        let normal = (&point - &self.center) / self.radius;
-        let mut hit_record = HitRecord::new(point, normal, root, false, Box::new(*self.material));
+#[cfg(test)]
+mod tests {
+    use super::{MovingSphere, Sphere};
+    use crate::{
+        color::Color, hittable::Hittable, material::Lambertian, point::Point, ray::Ray, utils::Rng,
+        vec3::Vec3,
+    };
+    use rand::SeedableRng;
+    use std::sync::Arc;
 
-        let outward_normal = (&hit_record.point - &self.center) / self.radius;
-        hit_record.set_face_normal(ray, outward_normal);
+    #[test]
+    fn uv() {
+        let uv = |x, y, z| Sphere::uv(&Point::new([x, y, z]));
+        assert_eq!(uv(-1.0, 0.0, 0.0), (0.0, 0.5));
+        assert_eq!(uv(0.0, 0.0, 1.0), (0.25, 0.5));
+        assert_eq!(uv(1.0, 0.0, 0.0), (0.5, 0.5));
+        assert_eq!(uv(0.0, 1.0, 0.0).1, 1.0);
+        assert_eq!(uv(0.0, -1.0, 0.0).1, 0.0);
+    }
+
+    #[test]
+    fn moving() {
+        let mut rng = Rng::seed_from_u64(0);
+        let material = Arc::new(Lambertian::new(Color::new([0.5, 0.5, 0.5])));
+        let sphere = MovingSphere::new(
+            (Point::new([0.0, 0.0, 0.0]), Point::new([0.0, 2.0, 0.0])),
+            (0.0, 1.0),
+            0.5,
+            material,
+        );
+        let ray = |time| {
+            Ray::new(
+                Point::new([0.0, 2.0, 5.0]),
+                Vec3::new([0.0, 0.0, -1.0]),
+                time,
+            )
+        };
+        assert!(sphere
+            .hit(&ray(0.0), 0.001, f64::INFINITY, &mut rng)
+            .is_none());
+        assert_eq!(
+            sphere
+                .hit(&ray(1.0), 0.001, f64::INFINITY, &mut rng)
+                .map(|rec| rec.t),
+            Some(4.5)
+        );
+        // Stays at the end of its path after the interval
+        assert!(sphere
+            .hit(&ray(3.0), 0.001, f64::INFINITY, &mut rng)
+            .is_some());
 
-        Some(hit_record)
+        let bbox = sphere.bounding_box().unwrap();
+        assert_eq!(bbox.minimum, Point::new([-0.5, -0.5, -0.5]));
+        assert_eq!(bbox.maximum, Point::new([0.5, 2.5, 0.5]));
     }
 }
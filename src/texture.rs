@@ -0,0 +1,333 @@
+use crate::{color::Color, perlin::Perlin, point::Point};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Spatially varying color, looked up by surface coordinates and hit point
+pub(crate) trait Texture: Send + Sync {
+    fn value(&self, u: f64, v: f64, point: &Point) -> Color;
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SolidColor {
+    color: Color,
+}
+
+impl SolidColor {
+    pub(crate) fn new(color: Color) -> Self {
+        Self { color }
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f64, _v: f64, _point: &Point) -> Color {
+        self.color
+    }
+}
+
+/// Checker pattern in 3D space, so it does not depend on the UV mapping.
+/// `scale` is the number of cells per unit length.
+pub(crate) struct CheckerTexture {
+    odd: Arc<dyn Texture>,
+    even: Arc<dyn Texture>,
+    scale: f64,
+}
+
+impl CheckerTexture {
+    pub(crate) fn new(odd: Arc<dyn Texture>, even: Arc<dyn Texture>, scale: f64) -> Self {
+        Self { odd, even, scale }
+    }
+
+    pub(crate) fn from_colors(odd: Color, even: Color, scale: f64) -> Self {
+        Self::new(
+            Arc::new(SolidColor::new(odd)),
+            Arc::new(SolidColor::new(even)),
+            scale,
+        )
+    }
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, u: f64, v: f64, point: &Point) -> Color {
+        let frequency = self.scale * std::f64::consts::PI;
+        let sines = (frequency * point.x()).sin()
+            * (frequency * point.y()).sin()
+            * (frequency * point.z()).sin();
+        if sines < 0.0 {
+            self.odd.value(u, v, point)
+        } else {
+            self.even.value(u, v, point)
+        }
+    }
+}
+
+/// How a `NoiseTexture` turns Perlin noise into a pattern
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum NoisePattern {
+    /// Plain smooth noise
+    Smooth,
+    /// Several octaves of noise, like a camouflage net
+    Turbulence,
+    /// Stripes along z, distorted by turbulence
+    Marble,
+    /// Rings around the y axis, distorted by turbulence
+    Wood,
+}
+
+/// Procedural texture, `scale` is the frequency of the pattern
+pub(crate) struct NoiseTexture {
+    noise: Perlin,
+    pattern: NoisePattern,
+    scale: f64,
+    color: Color,
+}
+
+impl NoiseTexture {
+    const TURBULENCE_DEPTH: usize = 7;
+
+    pub(crate) fn new(noise: Perlin, pattern: NoisePattern, scale: f64, color: Color) -> Self {
+        Self {
+            noise,
+            pattern,
+            scale,
+            color,
+        }
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f64, _v: f64, point: &Point) -> Color {
+        let scaled = *point * self.scale;
+        let intensity = match self.pattern {
+            NoisePattern::Smooth => 0.5 * (1.0 + self.noise.noise(&scaled)),
+            NoisePattern::Turbulence => self.noise.turbulence(&scaled, Self::TURBULENCE_DEPTH),
+            NoisePattern::Marble => {
+                let turbulence = self.noise.turbulence(point, Self::TURBULENCE_DEPTH);
+                0.5 * (1.0 + (scaled.z() + 10.0 * turbulence).sin())
+            }
+            NoisePattern::Wood => {
+                let radius = (scaled.x() * scaled.x() + scaled.z() * scaled.z()).sqrt();
+                let turbulence = self.noise.turbulence(point, Self::TURBULENCE_DEPTH);
+                let rings = radius + 2.0 * turbulence;
+                0.6 + 0.4 * (rings - rings.floor())
+            }
+        };
+        self.color * intensity
+    }
+}
+
+/// What happens to texture coordinates outside of `[0, 1]`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum WrapMode {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+impl WrapMode {
+    /// Maps a texel index onto `0..size`
+    fn apply(self, index: i64, size: usize) -> usize {
+        let size = size as i64;
+        let wrapped = match self {
+            Self::Repeat => index.rem_euclid(size),
+            Self::Clamp => index.clamp(0, size - 1),
+            Self::Mirror => {
+                let period = index.rem_euclid(2 * size);
+                if period < size {
+                    period
+                } else {
+                    2 * size - 1 - period
+                }
+            }
+        };
+        wrapped as usize
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct TextureError {
+    path: PathBuf,
+    source: image::ImageError,
+}
+
+impl fmt::Display for TextureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "failed to load texture '{}': {}",
+            self.path.display(),
+            self.source
+        )
+    }
+}
+
+/// Bilinearly filtered image, with `v = 0` at the bottom row
+pub(crate) struct ImageTexture {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+    wrap: WrapMode,
+}
+
+impl ImageTexture {
+    /// `pixels` are linear colors, stored row by row from the top
+    pub(crate) fn new(width: usize, height: usize, pixels: Vec<Color>, wrap: WrapMode) -> Self {
+        assert!(width > 0 && height > 0);
+        assert_eq!(pixels.len(), width * height);
+        Self {
+            width,
+            height,
+            pixels,
+            wrap,
+        }
+    }
+
+    /// Loads a PNG or JPEG file. Those store sRGB encoded values, which are
+    /// converted to linear colors here.
+    pub(crate) fn load(path: &Path, wrap: WrapMode) -> Result<Self, TextureError> {
+        let image = image::open(path)
+            .map_err(|source| TextureError {
+                path: path.to_path_buf(),
+                source,
+            })?
+            .into_rgb8();
+        let (width, height) = image.dimensions();
+        let pixels = image
+            .pixels()
+            .map(|pixel| {
+                Color::new([
+                    srgb_to_linear(pixel[0]),
+                    srgb_to_linear(pixel[1]),
+                    srgb_to_linear(pixel[2]),
+                ])
+            })
+            .collect();
+        Ok(Self::new(width as usize, height as usize, pixels, wrap))
+    }
+
+    fn texel(&self, x: i64, y: i64) -> Color {
+        let x = self.wrap.apply(x, self.width);
+        let y = self.wrap.apply(y, self.height);
+        self.pixels[y * self.width + x]
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _point: &Point) -> Color {
+        if !(u.is_finite() && v.is_finite()) {
+            // Degenerate transforms and mesh UVs can be infinite or NaN,
+            // those get the texel at the border they point to
+            let border = |c: f64, size: usize| {
+                if c.is_nan() {
+                    0
+                } else {
+                    (c.clamp(0.0, 1.0) * (size - 1) as f64).round() as usize
+                }
+            };
+            let x = border(u, self.width);
+            let y = border(1.0 - v, self.height);
+            return self.pixels[y * self.width + x];
+        }
+
+        let x = u * self.width as f64 - 0.5;
+        let y = (1.0 - v) * self.height as f64 - 0.5;
+        let (x_0, y_0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x_0, y - y_0);
+        // Huge UVs saturate, the next texel must not overflow
+        let (x_0, y_0) = (x_0 as i64, y_0 as i64);
+        let (x_1, y_1) = (x_0.saturating_add(1), y_0.saturating_add(1));
+
+        let top = self.texel(x_0, y_0) * (1.0 - fx) + self.texel(x_1, y_0) * fx;
+        let bottom = self.texel(x_0, y_1) * (1.0 - fx) + self.texel(x_1, y_1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+}
+
+fn srgb_to_linear(value: u8) -> f64 {
+    let value = value as f64 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CheckerTexture, ImageTexture, NoisePattern, NoiseTexture, Texture, WrapMode};
+    use crate::{color::Color, perlin::Perlin, point::Point, utils::Rng};
+    use rand::SeedableRng;
+
+    fn gray(value: f64) -> Color {
+        Color::new([value, value, value])
+    }
+
+    #[test]
+    fn wrap_modes() {
+        assert_eq!(WrapMode::Repeat.apply(-1, 4), 3);
+        assert_eq!(WrapMode::Repeat.apply(5, 4), 1);
+        assert_eq!(WrapMode::Clamp.apply(-1, 4), 0);
+        assert_eq!(WrapMode::Clamp.apply(9, 4), 3);
+        assert_eq!(WrapMode::Mirror.apply(-1, 4), 0);
+        assert_eq!(WrapMode::Mirror.apply(4, 4), 3);
+        assert_eq!(WrapMode::Mirror.apply(9, 4), 1);
+    }
+
+    #[test]
+    fn bilinear() {
+        // 2x1 image, black on the left and white on the right
+        let texture = ImageTexture::new(2, 1, vec![gray(0.0), gray(1.0)], WrapMode::Clamp);
+        let origin = Point::new([0.0, 0.0, 0.0]);
+        assert_eq!(texture.value(0.25, 0.5, &origin), gray(0.0));
+        assert_eq!(texture.value(0.5, 0.5, &origin), gray(0.5));
+        assert_eq!(texture.value(0.75, 0.5, &origin), gray(1.0));
+        assert_eq!(texture.value(1.5, 0.5, &origin), gray(1.0));
+    }
+
+    #[test]
+    fn rows_start_at_the_top() {
+        let texture = ImageTexture::new(1, 2, vec![gray(1.0), gray(0.0)], WrapMode::Clamp);
+        let origin = Point::new([0.0, 0.0, 0.0]);
+        assert_eq!(texture.value(0.5, 1.0, &origin), gray(1.0));
+        assert_eq!(texture.value(0.5, 0.0, &origin), gray(0.0));
+    }
+
+    #[test]
+    fn infinite_uvs() {
+        let texture = ImageTexture::new(2, 1, vec![gray(0.0), gray(1.0)], WrapMode::Repeat);
+        let origin = Point::new([0.0, 0.0, 0.0]);
+        assert_eq!(texture.value(f64::INFINITY, 0.5, &origin), gray(1.0));
+        assert_eq!(texture.value(f64::NEG_INFINITY, 0.5, &origin), gray(0.0));
+        assert_eq!(texture.value(f64::NAN, f64::NAN, &origin), gray(0.0));
+        assert_eq!(texture.value(0.75, f64::INFINITY, &origin), gray(1.0));
+        // Too large for texel indices, but still a color of the image
+        let huge = texture.value(1e300, -1e300, &origin).x();
+        assert!((0.0..=1.0).contains(&huge));
+    }
+
+    #[test]
+    fn noise_is_seeded() {
+        let texture = |seed| {
+            let noise = Perlin::new(&mut Rng::seed_from_u64(seed));
+            NoiseTexture::new(noise, NoisePattern::Marble, 4.0, gray(1.0))
+        };
+        let point = Point::new([0.25, 1.5, -0.75]);
+        assert_eq!(
+            texture(5).value(0.0, 0.0, &point),
+            texture(5).value(0.0, 0.0, &point)
+        );
+        let value = texture(5).value(0.0, 0.0, &point).x();
+        assert!((0.0..=1.0).contains(&value));
+    }
+
+    #[test]
+    fn checker() {
+        let checker = CheckerTexture::from_colors(gray(0.0), gray(1.0), 1.0);
+        let a = Point::new([0.5, 0.5, 0.5]);
+        let b = Point::new([1.5, 0.5, 0.5]);
+        assert_eq!(checker.value(0.0, 0.0, &a), gray(1.0));
+        assert_eq!(checker.value(0.0, 0.0, &b), gray(0.0));
+    }
+}
@@ -0,0 +1,139 @@
+use crate::color::Color;
+use clap::ValueEnum;
+
+/// Curve squeezing linear radiance into the displayable range
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum ToneMapper {
+    /// Everything brighter than white is clipped
+    Clamp,
+    /// `L / (1 + L)` on luminance, which never quite reaches white
+    Reinhard,
+    /// Reinhard that maps the `--white-point` luminance to white
+    ReinhardExtended,
+    /// Fit of the ACES filmic curve, contrasty with soft highlights
+    Aces,
+    /// John Hable's filmic curve from Uncharted 2
+    Hable,
+}
+
+/// Display transform of an HDR framebuffer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct ToneMapping {
+    pub(crate) operator: ToneMapper,
+    /// Brightness change in stops, every stop doubles the radiance
+    pub(crate) exposure: f64,
+    /// Luminance that becomes white with `ReinhardExtended`
+    pub(crate) white_point: f64,
+}
+
+impl ToneMapping {
+    /// Maps linear radiance to linear display values between 0 and 1
+    pub(crate) fn apply(&self, color: Color) -> Color {
+        let color = 2f64.powf(self.exposure) * &color;
+        let mapped = match self.operator {
+            ToneMapper::Clamp => color,
+            ToneMapper::Reinhard => scale_luminance(color, |l| l / (1.0 + l)),
+            ToneMapper::ReinhardExtended => {
+                let white_squared = self.white_point * self.white_point;
+                scale_luminance(color, |l| l * (1.0 + l / white_squared) / (1.0 + l))
+            }
+            ToneMapper::Aces => color.map(aces),
+            ToneMapper::Hable => color.map(|c| hable(2.0 * c) / hable(HABLE_WHITE)),
+        };
+        mapped.map(|c| c.clamp(0.0, 1.0))
+    }
+}
+
+/// Applies `curve` to the luminance and scales all channels alike, which
+/// keeps hues that per-channel curves would shift
+fn scale_luminance(color: Color, curve: impl Fn(f64) -> f64) -> Color {
+    let luminance = color.luminance();
+    if luminance > 0.0 {
+        curve(luminance) / luminance * &color
+    } else {
+        color
+    }
+}
+
+/// Krzysztof Narkowicz's fit, scaled to match the exposure of the full ACES
+/// reference transform
+fn aces(c: f64) -> f64 {
+    let c = 0.6 * c;
+    c * (2.51 * c + 0.03) / (c * (2.43 * c + 0.59) + 0.14)
+}
+
+/// Linear radiance that the Hable curve maps to white
+const HABLE_WHITE: f64 = 11.2;
+
+fn hable(c: f64) -> f64 {
+    const A: f64 = 0.15; // Shoulder strength
+    const B: f64 = 0.50; // Linear strength
+    const C: f64 = 0.10; // Linear angle
+    const D: f64 = 0.20; // Toe strength
+    const E: f64 = 0.02; // Toe numerator
+    const F: f64 = 0.30; // Toe denominator
+    (c * (A * c + C * B) + D * E) / (c * (A * c + B) + D * F) - E / F
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ToneMapper, ToneMapping};
+    use crate::color::Color;
+
+    fn grey(operator: ToneMapper, exposure: f64, value: f64) -> f64 {
+        let tone_mapping = ToneMapping {
+            operator,
+            exposure,
+            white_point: 4.0,
+        };
+        tone_mapping.apply(Color::new([value; 3])).x()
+    }
+
+    #[test]
+    fn curves() {
+        for operator in [
+            ToneMapper::Clamp,
+            ToneMapper::Reinhard,
+            ToneMapper::ReinhardExtended,
+            ToneMapper::Aces,
+            ToneMapper::Hable,
+        ] {
+            assert!(grey(operator, 0.0, 0.0).abs() < 1e-9, "{:?}", operator);
+            // Brighter radiance never gets darker, and stays displayable
+            let values: Vec<f64> = (0..100)
+                .map(|i| grey(operator, 0.0, 0.01 * (i * i) as f64))
+                .collect();
+            assert!(values.windows(2).all(|pair| pair[0] <= pair[1]));
+            assert!(values.iter().all(|&value| (0.0..=1.0).contains(&value)));
+        }
+
+        assert_eq!(grey(ToneMapper::Reinhard, 0.0, 1.0), 0.5);
+        assert!((grey(ToneMapper::ReinhardExtended, 0.0, 4.0) - 1.0).abs() < 1e-12);
+        assert!(grey(ToneMapper::Hable, 0.0, 5.6) > 0.999);
+        // Unlike clamping, the filmic curves keep highlights apart
+        assert_eq!(grey(ToneMapper::Clamp, 0.0, 2.0), 1.0);
+        assert!(grey(ToneMapper::Aces, 0.0, 2.0) < grey(ToneMapper::Aces, 0.0, 4.0));
+    }
+
+    #[test]
+    fn exposure_in_stops() {
+        assert_eq!(grey(ToneMapper::Clamp, 1.0, 0.2), 0.4);
+        assert_eq!(grey(ToneMapper::Clamp, -2.0, 0.8), 0.2);
+        assert_eq!(
+            grey(ToneMapper::Reinhard, 1.0, 0.5),
+            grey(ToneMapper::Reinhard, 0.0, 1.0)
+        );
+    }
+
+    #[test]
+    fn reinhard_keeps_hue() {
+        let tone_mapping = ToneMapping {
+            operator: ToneMapper::Reinhard,
+            exposure: 0.0,
+            white_point: 4.0,
+        };
+        let mapped = tone_mapping.apply(Color::new([0.8, 0.4, 0.2]));
+        assert!((mapped.x() / mapped.y() - 2.0).abs() < 1e-12);
+        assert!((mapped.y() / mapped.z() - 2.0).abs() < 1e-12);
+    }
+}
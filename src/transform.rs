@@ -0,0 +1,313 @@
+use crate::{
+    aabb::Aabb,
+    hittable::{HitRecord, Hittable},
+    point::Point,
+    ray::Ray,
+    utils::Rng,
+    vec3::Vec3,
+};
+use std::sync::Arc;
+
+type Matrix = [[f64; 4]; 4];
+
+const IDENTITY: Matrix = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut product = [[0.0; 4]; 4];
+    for (i, row) in product.iter_mut().enumerate() {
+        for (j, cell) in row.iter_mut().enumerate() {
+            *cell = (0..4).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    product
+}
+
+/// Affine 4x4 transform that keeps track of its own inverse, so it never has
+/// to invert a matrix
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Transform {
+    matrix: Matrix,
+    inverse: Matrix,
+}
+
+impl Transform {
+    pub(crate) fn identity() -> Self {
+        Self {
+            matrix: IDENTITY,
+            inverse: IDENTITY,
+        }
+    }
+
+    pub(crate) fn translation(offset: Vec3) -> Self {
+        let mut matrix = IDENTITY;
+        let mut inverse = IDENTITY;
+        for axis in 0..3 {
+            matrix[axis][3] = offset[axis];
+            inverse[axis][3] = -offset[axis];
+        }
+        Self { matrix, inverse }
+    }
+
+    /// Counter-clockwise rotation around `axis` when looking against it
+    pub(crate) fn rotation(axis: Vec3, degrees: f64) -> Self {
+        let axis = axis.as_unit_vec();
+        let (x, y, z) = (axis.x(), axis.y(), axis.z());
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let t = 1.0 - cos;
+
+        let matrix = [
+            [
+                t * x * x + cos,
+                t * x * y - sin * z,
+                t * x * z + sin * y,
+                0.0,
+            ],
+            [
+                t * x * y + sin * z,
+                t * y * y + cos,
+                t * y * z - sin * x,
+                0.0,
+            ],
+            [
+                t * x * z - sin * y,
+                t * y * z + sin * x,
+                t * z * z + cos,
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ];
+        // Rotations are orthogonal, the inverse is the transpose
+        let mut inverse = IDENTITY;
+        for (i, row) in inverse.iter_mut().enumerate().take(3) {
+            for (j, cell) in row.iter_mut().enumerate().take(3) {
+                *cell = matrix[j][i];
+            }
+        }
+        Self { matrix, inverse }
+    }
+
+    /// Scales along each axis, all factors must be non-zero
+    pub(crate) fn scaling(factors: Vec3) -> Self {
+        let mut matrix = IDENTITY;
+        let mut inverse = IDENTITY;
+        for axis in 0..3 {
+            assert!(factors[axis] != 0.0, "cannot scale by zero");
+            matrix[axis][axis] = factors[axis];
+            inverse[axis][axis] = 1.0 / factors[axis];
+        }
+        Self { matrix, inverse }
+    }
+
+    /// This transform followed by `next`
+    pub(crate) fn then(&self, next: &Transform) -> Self {
+        Self {
+            matrix: multiply(&next.matrix, &self.matrix),
+            inverse: multiply(&self.inverse, &next.inverse),
+        }
+    }
+
+    pub(crate) fn inverse(&self) -> Self {
+        Self {
+            matrix: self.inverse,
+            inverse: self.matrix,
+        }
+    }
+
+    pub(crate) fn point(&self, point: &Point) -> Point {
+        let m = &self.matrix;
+        Point::new(
+            [0, 1, 2]
+                .map(|i| m[i][0] * point.x() + m[i][1] * point.y() + m[i][2] * point.z() + m[i][3]),
+        )
+    }
+
+    pub(crate) fn vector(&self, vector: &Vec3) -> Vec3 {
+        let m = &self.matrix;
+        Vec3::new(
+            [0, 1, 2].map(|i| m[i][0] * vector.x() + m[i][1] * vector.y() + m[i][2] * vector.z()),
+        )
+    }
+
+    /// Normals are transformed by the inverse transpose, which keeps them
+    /// perpendicular to the surface under non-uniform scaling. The result is
+    /// not normalized.
+    pub(crate) fn normal(&self, normal: &Vec3) -> Vec3 {
+        let m = &self.inverse;
+        Vec3::new(
+            [0, 1, 2].map(|i| m[0][i] * normal.x() + m[1][i] * normal.y() + m[2][i] * normal.z()),
+        )
+    }
+
+    /// How much the transform scales volumes, the determinant of its linear
+    /// part
+    fn volume_scale(&self) -> f64 {
+        let m = &self.matrix;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    /// The box around the eight transformed corners of `bbox`
+    pub(crate) fn bounding_box(&self, bbox: &Aabb) -> Aabb {
+        let corner = |i: usize| {
+            let pick = |axis: usize| {
+                if i & (1 << axis) == 0 {
+                    bbox.minimum[axis]
+                } else {
+                    bbox.maximum[axis]
+                }
+            };
+            self.point(&Point::new([pick(0), pick(1), pick(2)]))
+        };
+        (1..8).fold(Aabb::new(corner(0), corner(0)), |acc, i| {
+            Aabb::surrounding(&acc, &Aabb::new(corner(i), corner(i)))
+        })
+    }
+}
+
+/// A hittable placed in the world by a transform. The object is shared, so
+/// one model can be instanced in several places.
+pub(crate) struct Transformed {
+    object: Arc<dyn Hittable>,
+    transform: Transform,
+    bbox: Option<Aabb>,
+}
+
+impl Transformed {
+    pub(crate) fn new(object: Arc<dyn Hittable>, transform: Transform) -> Self {
+        let bbox = object
+            .bounding_box()
+            .map(|bbox| transform.bounding_box(&bbox));
+        Self {
+            object,
+            transform,
+            bbox,
+        }
+    }
+}
+
+impl Hittable for Transformed {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut Rng) -> Option<HitRecord<'_>> {
+        // The direction is not normalized, so `t` is the same in both spaces
+        let to_object = self.transform.inverse();
+        let object_ray = Ray::new(
+            to_object.point(&ray.origin),
+            to_object.vector(&ray.direction),
+            ray.time,
+        );
+
+        let mut hit_record = self.object.hit(&object_ray, t_min, t_max, rng)?;
+        hit_record.point = self.transform.point(&hit_record.point);
+        hit_record.normal = self.transform.normal(&hit_record.normal).as_unit_vec();
+        Some(hit_record)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bbox
+    }
+
+    fn pdf_value(&self, origin: &Point, direction: &Vec3, rng: &mut Rng) -> f64 {
+        let to_object = self.transform.inverse();
+        let direction = direction.as_unit_vec();
+        let object_direction = to_object.vector(&direction);
+        let density = self
+            .object
+            .pdf_value(&to_object.point(origin), &object_direction, rng);
+        // Unless the transform is rigid, directions are squeezed together in
+        // some places and spread out in others, which changes the density by
+        // the Jacobian |det A| / |A d|^3 of d -> normalize(A d)
+        density * to_object.volume_scale().abs() / object_direction.len().powi(3)
+    }
+
+    fn random(&self, origin: &Point, rng: &mut Rng) -> Vec3 {
+        let to_object = self.transform.inverse();
+        let direction = self.object.random(&to_object.point(origin), rng);
+        self.transform.vector(&direction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Transform, Transformed};
+    use crate::{
+        aabb::Aabb, color::Color, hittable::Hittable, material::Lambertian, point::Point, ray::Ray,
+        sphere::Sphere, utils::Rng, vec3::Vec3,
+    };
+    use rand::SeedableRng;
+    use std::sync::Arc;
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).len() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn compose_and_invert() {
+        let transform = Transform::scaling(Vec3::new([2.0, 1.0, 1.0]))
+            .then(&Transform::rotation(Vec3::new([0.0, 1.0, 0.0]), 90.0))
+            .then(&Transform::translation(Vec3::new([0.0, 0.0, 5.0])));
+
+        let point = Point::new([1.0, 2.0, 0.0]);
+        // Scaled to x = 2, rotated onto -z, then moved along z
+        assert_close(transform.point(&point), Point::new([0.0, 2.0, 3.0]));
+        assert_close(transform.inverse().point(&transform.point(&point)), point);
+        // Vectors are not translated
+        assert_close(
+            transform.vector(&Vec3::new([1.0, 0.0, 0.0])),
+            Vec3::new([0.0, 0.0, -2.0]),
+        );
+    }
+
+    #[test]
+    fn normals_under_non_uniform_scale() {
+        // A 45 degree slope, squashed vertically, gets a flatter slope and a
+        // steeper normal
+        let transform = Transform::scaling(Vec3::new([1.0, 0.5, 1.0]));
+        let tangent = transform.vector(&Vec3::new([1.0, 1.0, 0.0]));
+        let normal = transform.normal(&Vec3::new([-1.0, 1.0, 0.0]));
+        assert!(tangent.dot(&normal).abs() < 1e-12);
+        assert_close(normal, Vec3::new([-1.0, 2.0, 0.0]));
+    }
+
+    #[test]
+    fn transformed_sphere() {
+        let mut rng = Rng::seed_from_u64(0);
+        let material = Arc::new(Lambertian::new(Color::new([0.5, 0.5, 0.5])));
+        let sphere = Arc::new(Sphere::new(Point::new([0.0, 0.0, 0.0]), 1.0, material));
+        let transform = Transform::scaling(Vec3::new([2.0, 1.0, 1.0]))
+            .then(&Transform::translation(Vec3::new([0.0, 0.0, -5.0])));
+        let ellipsoid = Transformed::new(sphere, transform);
+
+        let ray = Ray::new(
+            Point::new([0.0, 0.0, 0.0]),
+            Vec3::new([0.0, 0.0, -1.0]),
+            0.0,
+        );
+        let hit = ellipsoid.hit(&ray, 0.001, f64::INFINITY, &mut rng).unwrap();
+        assert!((hit.t - 4.0).abs() < 1e-9);
+        assert_close(hit.point, Point::new([0.0, 0.0, -4.0]));
+        assert_close(hit.normal, Vec3::new([0.0, 0.0, 1.0]));
+        assert!(hit.front_face);
+
+        let side = Ray::new(
+            Point::new([1.5, 0.0, 0.0]),
+            Vec3::new([0.0, 0.0, -1.0]),
+            0.0,
+        );
+        assert!(ellipsoid
+            .hit(&side, 0.001, f64::INFINITY, &mut rng)
+            .is_some());
+
+        assert_eq!(
+            ellipsoid.bounding_box(),
+            Some(Aabb::new(
+                Point::new([-2.0, -1.0, -6.0]),
+                Point::new([2.0, 1.0, -4.0])
+            ))
+        );
+    }
+}
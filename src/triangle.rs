@@ -0,0 +1,303 @@
+use crate::{
+    aabb::Aabb,
+    hittable::{HitRecord, Hittable},
+    material::Material,
+    point::Point,
+    ray::Ray,
+    utils::{self, Rng},
+    vec3::Vec3,
+};
+use std::sync::Arc;
+
+/// Vertex attributes shared by the triangles of one or more meshes
+#[derive(Debug, Clone, Default)]
+pub(crate) struct VertexBuffers {
+    pub(crate) positions: Vec<Point>,
+    pub(crate) normals: Vec<Vec3>,
+    pub(crate) uvs: Vec<(f64, f64)>,
+}
+
+impl VertexBuffers {
+    /// Whether every index of `face` points into these buffers
+    pub(crate) fn contains(&self, face: &Face) -> bool {
+        let within = |indices: Option<[usize; 3]>, len: usize| {
+            indices.is_none_or(|indices| indices.iter().all(|i| *i < len))
+        };
+        within(Some(face.positions), self.positions.len())
+            && within(face.normals, self.normals.len())
+            && within(face.uvs, self.uvs.len())
+    }
+}
+
+/// Indices of a triangle's corners into `VertexBuffers`. Normals and UVs are
+/// optional and indexed separately, like in OBJ files.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Face {
+    pub(crate) positions: [usize; 3],
+    pub(crate) normals: Option<[usize; 3]>,
+    pub(crate) uvs: Option<[usize; 3]>,
+}
+
+impl Face {
+    pub(crate) fn new(positions: [usize; 3]) -> Self {
+        Self {
+            positions,
+            normals: None,
+            uvs: None,
+        }
+    }
+}
+
+/// Triangle intersected with the Möller–Trumbore algorithm.
+///
+/// Without vertex normals the surface is flat shaded and faces the side the
+/// corners appear counter-clockwise from. Without UVs the barycentric
+/// coordinates are used as UVs.
+pub(crate) struct Triangle {
+    vertices: Arc<VertexBuffers>,
+    face: Face,
+    material: Arc<dyn Material>,
+}
+
+impl Triangle {
+    pub(crate) fn new(
+        vertices: Arc<VertexBuffers>,
+        face: Face,
+        material: Arc<dyn Material>,
+    ) -> Self {
+        assert!(vertices.contains(&face), "face index out of bounds");
+        Self {
+            vertices,
+            face,
+            material,
+        }
+    }
+
+    fn corners(&self) -> [Point; 3] {
+        self.face.positions.map(|i| self.vertices.positions[i])
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _rng: &mut Rng) -> Option<HitRecord<'_>> {
+        let [p_0, p_1, p_2] = self.corners();
+        let edge_1 = p_1 - p_0;
+        let edge_2 = p_2 - p_0;
+
+        let p = ray.direction.cross(&edge_2);
+        let determinant = edge_1.dot(&p);
+        // The ray is parallel to the triangle's plane
+        if determinant.abs() < 1e-12 {
+            return None;
+        }
+        let inv_determinant = 1.0 / determinant;
+
+        let s = ray.origin - p_0;
+        let u = s.dot(&p) * inv_determinant;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = s.cross(&edge_1);
+        let v = ray.direction.dot(&q) * inv_determinant;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = edge_2.dot(&q) * inv_determinant;
+        if t < t_min || t_max < t {
+            return None;
+        }
+
+        let w = 1.0 - u - v;
+        let geometric = edge_1.cross(&edge_2).as_unit_vec();
+        let shading = self.face.normals.map(|[n_0, n_1, n_2]| {
+            let normals = &self.vertices.normals;
+            (normals[n_0] * w + normals[n_1] * u + normals[n_2] * v).as_unit_vec()
+        });
+        let uv = match self.face.uvs {
+            Some([t_0, t_1, t_2]) => {
+                let uvs = &self.vertices.uvs;
+                (
+                    uvs[t_0].0 * w + uvs[t_1].0 * u + uvs[t_2].0 * v,
+                    uvs[t_0].1 * w + uvs[t_1].1 * u + uvs[t_2].1 * v,
+                )
+            }
+            None => (u, v),
+        };
+
+        // Vertex normals decide which side is the outside, the geometric
+        // normal decides which side the ray is on
+        let (outward, shading) = match shading {
+            Some(shading) if geometric.dot(&shading) < 0.0 => (-geometric, shading),
+            Some(shading) => (geometric, shading),
+            None => (geometric, geometric),
+        };
+        let mut hit_record =
+            HitRecord::new(ray.at(t), outward, t, uv, false, self.material.as_ref());
+        hit_record.set_face_normal(ray, outward);
+        hit_record.normal = if hit_record.front_face {
+            shading
+        } else {
+            -shading
+        };
+        Some(hit_record)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        // Pad the box so triangles in an axis-aligned plane are not flat
+        const PADDING: f64 = 0.0001;
+        let [p_0, p_1, p_2] = self.corners();
+        let padding = Vec3::new([PADDING, PADDING, PADDING]);
+        Some(Aabb::new(
+            p_0.min(&p_1).min(&p_2) - padding,
+            p_0.max(&p_1).max(&p_2) + padding,
+        ))
+    }
+
+    /// Uniform over the area, converted to solid angle
+    fn pdf_value(&self, origin: &Point, direction: &Vec3, rng: &mut Rng) -> f64 {
+        let ray = Ray::new(*origin, *direction, 0.0);
+        match self.hit(&ray, 0.001, f64::INFINITY, rng) {
+            Some(hit_record) => {
+                // The geometric normal, the record holds the shading normal
+                let [p_0, p_1, p_2] = self.corners();
+                let normal = (p_1 - p_0).cross(&(p_2 - p_0));
+                let area = 0.5 * normal.len();
+                let distance_squared = hit_record.t * hit_record.t * direction.len_square();
+                let cosine = direction.dot(&normal).abs() / (direction.len() * normal.len());
+                distance_squared / (cosine * area)
+            }
+            None => 0.0,
+        }
+    }
+
+    fn random(&self, origin: &Point, rng: &mut Rng) -> Vec3 {
+        let [p_0, p_1, p_2] = self.corners();
+        let mut u = utils::random_float(rng);
+        let mut v = utils::random_float(rng);
+        // Fold the far half of the parallelogram back onto the triangle
+        if u + v > 1.0 {
+            u = 1.0 - u;
+            v = 1.0 - v;
+        }
+        p_0 + (p_1 - p_0) * u + (p_2 - p_0) * v - *origin
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Face, Triangle, VertexBuffers};
+    use crate::{
+        color::Color, hittable::Hittable, material::Lambertian, point::Point, ray::Ray, utils::Rng,
+        vec3::Vec3,
+    };
+    use rand::SeedableRng;
+    use std::sync::Arc;
+
+    fn material() -> Arc<Lambertian> {
+        Arc::new(Lambertian::new(Color::new([0.5, 0.5, 0.5])))
+    }
+
+    fn down(x: f64, z: f64) -> Ray {
+        Ray::new(Point::new([x, 1.0, z]), Vec3::new([0.0, -1.0, 0.0]), 0.0)
+    }
+
+    // Counter-clockwise when seen from above, so it faces up
+    fn corners() -> [Point; 3] {
+        [
+            Point::new([0.0, 0.0, 0.0]),
+            Point::new([0.0, 0.0, 1.0]),
+            Point::new([1.0, 0.0, 0.0]),
+        ]
+    }
+
+    #[test]
+    fn flat() {
+        let mut rng = Rng::seed_from_u64(0);
+        let vertices = VertexBuffers {
+            positions: corners().to_vec(),
+            ..VertexBuffers::default()
+        };
+        let triangle = Triangle::new(Arc::new(vertices), Face::new([0, 1, 2]), material());
+
+        let hit = triangle
+            .hit(&down(0.25, 0.25), 0.001, f64::INFINITY, &mut rng)
+            .unwrap();
+        assert_eq!(hit.t, 1.0);
+        assert!(hit.front_face);
+        assert_eq!(hit.normal, Vec3::new([0.0, 1.0, 0.0]));
+        assert_eq!((hit.u, hit.v), (0.25, 0.25));
+
+        let up = Ray::new(
+            Point::new([0.25, -1.0, 0.25]),
+            Vec3::new([0.0, 1.0, 0.0]),
+            0.0,
+        );
+        let hit = triangle.hit(&up, 0.001, f64::INFINITY, &mut rng).unwrap();
+        assert!(!hit.front_face);
+        assert_eq!(hit.normal, Vec3::new([0.0, -1.0, 0.0]));
+
+        assert!(triangle
+            .hit(&down(0.75, 0.75), 0.001, f64::INFINITY, &mut rng)
+            .is_none());
+        assert!(triangle
+            .hit(&down(-0.1, 0.5), 0.001, f64::INFINITY, &mut rng)
+            .is_none());
+        let parallel = Ray::new(
+            Point::new([-1.0, 0.0, 0.25]),
+            Vec3::new([1.0, 0.0, 0.0]),
+            0.0,
+        );
+        assert!(triangle
+            .hit(&parallel, 0.001, f64::INFINITY, &mut rng)
+            .is_none());
+    }
+
+    #[test]
+    fn interpolated_attributes() {
+        let mut rng = Rng::seed_from_u64(0);
+        let vertices = VertexBuffers {
+            positions: corners().to_vec(),
+            normals: vec![
+                Vec3::new([0.0, 1.0, 0.0]),
+                Vec3::new([1.0, 0.0, 0.0]),
+                Vec3::new([0.0, 0.0, 1.0]),
+            ],
+            uvs: vec![(0.0, 0.0), (0.0, 1.0), (1.0, 0.0)],
+        };
+        let face = Face {
+            positions: [0, 1, 2],
+            normals: Some([0, 0, 0]),
+            uvs: Some([0, 1, 2]),
+        };
+        let triangle = Triangle::new(Arc::new(vertices.clone()), face, material());
+        let hit = triangle
+            .hit(&down(0.5, 0.25), 0.001, f64::INFINITY, &mut rng)
+            .unwrap();
+        assert_eq!((hit.u, hit.v), (0.5, 0.25));
+
+        let smooth = Face {
+            normals: Some([1, 1, 2]),
+            ..face
+        };
+        let triangle = Triangle::new(Arc::new(vertices), smooth, material());
+        let hit = triangle
+            .hit(&down(0.5, 0.0), 0.001, f64::INFINITY, &mut rng)
+            .unwrap();
+        let expected = Vec3::new([1.0, 0.0, 1.0]).as_unit_vec();
+        assert!((hit.normal - expected).len() < 1e-12);
+    }
+
+    #[test]
+    fn bounds_checks() {
+        let vertices = VertexBuffers {
+            positions: corners().to_vec(),
+            ..VertexBuffers::default()
+        };
+        assert!(vertices.contains(&Face::new([0, 1, 2])));
+        assert!(!vertices.contains(&Face::new([0, 1, 3])));
+        assert!(!vertices.contains(&Face {
+            normals: Some([0, 0, 0]),
+            ..Face::new([0, 1, 2])
+        }));
+    }
+}
@@ -3,53 +3,81 @@ use crate::{
     hittable::HittableList,
     material::{Dielectric, Lambertian, Metal},
     point::Point,
-    sphere::Sphere,
+    sphere::{MovingSphere, Sphere},
+    vec3::Vec3,
 };
-use rand::Rng;
+use rand::{Rng as _, SeedableRng};
+use rand_pcg::Pcg64Mcg;
+use std::sync::Arc;
 
-pub(crate) fn random_float() -> f64 {
-    let mut rng = rand::thread_rng();
+/// The random number generator used throughout the renderer. PCG is portable,
+/// so the same seed gives the same image on every machine.
+pub(crate) type Rng = Pcg64Mcg;
+
+pub(crate) fn random_float(rng: &mut Rng) -> f64 {
     rng.gen::<f64>()
 }
 
-pub(crate) fn random_range(min: f64, max: f64) -> f64 {
-    let mut rng = rand::thread_rng();
+pub(crate) fn random_range(rng: &mut Rng, min: f64, max: f64) -> f64 {
     rng.gen_range(min..max)
 }
 
-pub(crate) fn random_scene(world: &mut HittableList) {
+/// Independent stream for one sample of a pixel, so the image does not
+/// depend on which thread rendered which pixel or in what order, and a
+/// render can be resumed knowing only how many samples every pixel took
+pub(crate) fn sample_rng(seed: u64, x: usize, y: usize, sample: u32) -> Rng {
+    let pixel = ((y as u64) << 32) | x as u64;
+    let pixel_seed = splitmix64(seed ^ splitmix64(pixel));
+    Rng::seed_from_u64(splitmix64(pixel_seed ^ sample as u64))
+}
+
+fn splitmix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+pub(crate) fn random_scene(world: &mut HittableList, rng: &mut Rng) {
     let ground_material = Lambertian::new(Color::new([0.5, 0.5, 0.5]));
     let ground = Sphere::new(
         Point::new([0.0, -1000.0, 0.0]),
         1000.0,
-        Box::new(ground_material),
+        Arc::new(ground_material),
     );
     world.add(Box::new(ground));
 
     for a in -11..11 {
         for b in -11..11 {
             let center = Point::new([
-                a as f64 + 0.9 * random_float(),
+                a as f64 + 0.9 * random_float(rng),
                 0.2,
-                b as f64 + 0.9 * random_float(),
+                b as f64 + 0.9 * random_float(rng),
             ]);
 
-            let choose_mat = random_float();
+            let choose_mat = random_float(rng);
             if (center - Point::new([4.0, 0.2, 0.0])).len() > 0.9 {
                 if choose_mat < 0.8 {
-                    let albedo = &Color::random(0.0, 1.0) * &Color::random(0.0, 1.0);
+                    let albedo = &Color::random(rng, 0.0, 1.0) * &Color::random(rng, 0.0, 1.0);
                     let sphere_material = Lambertian::new(albedo);
-                    let sphere = Sphere::new(center, 0.2, Box::new(sphere_material));
+                    // Diffuse spheres bounce up while the shutter is open
+                    let center_1 = center + Vec3::new([0.0, random_range(rng, 0.0, 0.5), 0.0]);
+                    let sphere = MovingSphere::new(
+                        (center, center_1),
+                        (0.0, 1.0),
+                        0.2,
+                        Arc::new(sphere_material),
+                    );
                     world.add(Box::new(sphere));
                 } else if choose_mat < 0.95 {
-                    let albedo = Color::random(0.5, 1.0);
-                    let fuzz = random_range(0.0, 0.5);
+                    let albedo = Color::random(rng, 0.5, 1.0);
+                    let fuzz = random_range(rng, 0.0, 0.5);
                     let sphere_material = Metal::new(albedo, fuzz);
-                    let sphere = Sphere::new(center, 0.2, Box::new(sphere_material));
+                    let sphere = Sphere::new(center, 0.2, Arc::new(sphere_material));
                     world.add(Box::new(sphere));
                 } else {
                     let sphere_material = Dielectric::new(1.5);
-                    let sphere = Sphere::new(center, 0.2, Box::new(sphere_material));
+                    let sphere = Sphere::new(center, 0.2, Arc::new(sphere_material));
                     world.add(Box::new(sphere));
                 }
             }
@@ -57,14 +85,14 @@ pub(crate) fn random_scene(world: &mut HittableList) {
     }
 
     let material_1 = Dielectric::new(1.5);
-    let sphere_1 = Sphere::new(Point::new([0.0, 1.0, 0.0]), 1.0, Box::new(material_1));
+    let sphere_1 = Sphere::new(Point::new([0.0, 1.0, 0.0]), 1.0, Arc::new(material_1));
     world.add(Box::new(sphere_1));
 
     let material_2 = Lambertian::new(Color::new([0.4, 0.2, 0.1]));
-    let sphere_2 = Sphere::new(Point::new([-4.0, 1.0, 0.0]), 1.0, Box::new(material_2));
+    let sphere_2 = Sphere::new(Point::new([-4.0, 1.0, 0.0]), 1.0, Arc::new(material_2));
     world.add(Box::new(sphere_2));
 
     let material_3 = Metal::new(Color::new([0.7, 0.6, 0.5]), 0.0);
-    let sphere_3 = Sphere::new(Point::new([4.0, 1.0, 0.0]), 1.0, Box::new(material_3));
+    let sphere_3 = Sphere::new(Point::new([4.0, 1.0, 0.0]), 1.0, Arc::new(material_3));
     world.add(Box::new(sphere_3));
 }
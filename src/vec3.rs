@@ -1,4 +1,4 @@
-use rand::Rng;
+use crate::utils::{self, Rng};
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub struct Vec3 {
@@ -53,29 +53,58 @@ impl Vec3 {
     }
 
     #[inline]
-    pub(crate) fn random(min: f64, max: f64) -> Self {
-        let mut rng = rand::thread_rng();
+    pub(crate) fn min(&self, other: &Self) -> Vec3 {
+        Vec3 {
+            data: [
+                self.data[0].min(other.data[0]),
+                self.data[1].min(other.data[1]),
+                self.data[2].min(other.data[2]),
+            ],
+        }
+    }
+
+    #[inline]
+    pub(crate) fn max(&self, other: &Self) -> Vec3 {
+        Vec3 {
+            data: [
+                self.data[0].max(other.data[0]),
+                self.data[1].max(other.data[1]),
+                self.data[2].max(other.data[2]),
+            ],
+        }
+    }
+
+    /// Applies `f` to every component
+    #[inline]
+    pub(crate) fn map(self, f: impl FnMut(f64) -> f64) -> Vec3 {
+        Vec3 {
+            data: self.data.map(f),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn random(rng: &mut Rng, min: f64, max: f64) -> Self {
         Self {
             data: [
-                rng.gen_range(min..max),
-                rng.gen_range(min..max),
-                rng.gen_range(min..max),
+                utils::random_range(rng, min, max),
+                utils::random_range(rng, min, max),
+                utils::random_range(rng, min, max),
             ],
         }
     }
 
     #[inline]
-    pub(crate) fn random_in_unit_sphere() -> Self {
-        let mut p = Self::random(-1.0, 1.0);
+    pub(crate) fn random_in_unit_sphere(rng: &mut Rng) -> Self {
+        let mut p = Self::random(rng, -1.0, 1.0);
         while p.len_square() >= 1.0 {
-            p = Self::random(-1.0, 1.0);
+            p = Self::random(rng, -1.0, 1.0);
         }
         p
     }
 
     #[inline]
-    pub(crate) fn _random_in_hemisphere(normal: &Self) -> Self {
-        let in_unit_sphere = Self::random_in_unit_sphere();
+    pub(crate) fn _random_in_hemisphere(normal: &Self, rng: &mut Rng) -> Self {
+        let in_unit_sphere = Self::random_in_unit_sphere(rng);
         if in_unit_sphere.dot(normal) > 0.0 {
             // in same hemisphere as the normal
             in_unit_sphere
@@ -85,8 +114,8 @@ impl Vec3 {
     }
 
     #[inline]
-    pub(crate) fn _random_unit_vec() -> Self {
-        Self::random_in_unit_sphere().as_unit_vec()
+    pub(crate) fn random_unit_vec(rng: &mut Rng) -> Self {
+        Self::random_in_unit_sphere(rng).as_unit_vec()
     }
 
     #[inline]
@@ -108,13 +137,22 @@ impl Vec3 {
 
     #[inline]
     pub(crate) fn refract(uv: &Vec3, n: &Vec3, etai_over_etat: f64) -> Vec3 {
-        let cos_theta = (-uv).dot(&n).min(1.0);
+        let cos_theta = (-uv).dot(n).min(1.0);
         let r_out_perp = etai_over_etat * &(uv + &(n * cos_theta));
         let r_out_parallel = -(1.0 - r_out_perp.len_square()).abs().sqrt() * n;
         r_out_perp + r_out_parallel
     }
 }
 
+impl std::ops::Index<usize> for Vec3 {
+    type Output = f64;
+
+    #[inline]
+    fn index(&self, axis: usize) -> &f64 {
+        &self.data[axis]
+    }
+}
+
 impl std::ops::Add for Vec3 {
     type Output = Self;
 
@@ -146,6 +184,7 @@ impl std::ops::Sub for Vec3 {
     type Output = Self;
 
     #[inline]
+    #[allow(clippy::op_ref)]
     fn sub(self, other: Self) -> Self::Output {
         &self - &other
     }
@@ -214,6 +253,7 @@ impl std::ops::Mul<f64> for Vec3 {
     type Output = Self;
 
     #[inline]
+    #[allow(clippy::op_ref)]
     fn mul(self, scalar: f64) -> Self::Output {
         &self * scalar
     }
@@ -336,6 +376,20 @@ mod tests {
         assert_eq!(v.cross(&u), Vec3::new([-5.0, 10.0, -5.0]));
     }
 
+    #[test]
+    fn index() {
+        let v = Vec3::new([1.0, 2.0, 3.0]);
+        assert_eq!([v[0], v[1], v[2]], [1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn min_max() {
+        let v = Vec3::new([1.0, 5.0, -3.0]);
+        let u = Vec3::new([2.0, 1.0, -4.0]);
+        assert_eq!(v.min(&u), Vec3::new([1.0, 1.0, -4.0]));
+        assert_eq!(v.max(&u), Vec3::new([2.0, 5.0, -3.0]));
+    }
+
     #[test]
     fn unit_vec() {
         let v = Vec3::new([4.3, 9.5, 12343.3]).as_unit_vec();